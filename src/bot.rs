@@ -1,21 +1,60 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
 
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sd_notify::NotifyState;
 use sysinfo::System;
 use teloxide::dispatching::dialogue::GetChatId;
 use teloxide::{
     dispatching::UpdateFilterExt,
+    error_handlers::LoggingErrorHandler,
     payloads,
+    update_listeners::Polling,
     prelude::*,
-    requests::JsonRequest,
-    types::{Me, Message, MessageId, Recipient, User},
-    utils::command::BotCommands,
+    requests::{HasPayload, JsonRequest},
+    RequestError,
+    types::{
+        AllowedUpdate, BotCommandScope, CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Me, Message,
+        MessageId, MessageReactionUpdated, ReactionType, Recipient, ReplyParameters, ThreadId,
+        User,
+    },
+    utils::{command::BotCommands, html},
 };
 use tokio::sync::Mutex;
+use tracing::Instrument;
 
 use crate::{
-    BlacklistResult, CallResult, LeaveResult, ReplaceUserExt, UnblacklistResult, UserRegister, call_map::CallMap, cmd::{self, Command}, question::{QUESTION_HINT, QUESTION_MAP}
+    BlacklistResult, CallResult, Config, EscalationPolicy, LeaveResult, Locale, RegisterAction, RegisterVia, UnblacklistResult, UserRecord, UserRegister, call_map::CallMap, calls::{ActiveCall, ActiveCalls, CallEnd, Rsvp}, cmd::{self, Command}, deletion::DeletionQueue, draw, health::{self, HEALTH, Probe}, history::History, lobby::{self, JoinResult, Lobbies, Lobby, LobbyAction, LobbyLeaveResult, ReadyResult}, metrics::METRICS, migration, notify::{self, Subscribers}, question::QUESTION_MAP, reporter::ErrorReporter, schedule::{self, Clock, Schedule, ScheduleSpec, Schedules, SystemClock}, storage::{State, Storage}, template::{Args, TextCtx, Templates}
 };
 
+/// How often the scheduler looks for due calls and expired ready checks.
+const TICK: Duration = Duration::from_secs(5);
+
+/// How long lobby members get to confirm a ready check.
+const READY_CHECK: Duration = Duration::from_secs(60);
+
+/// Registration events shown by `/whoregisteredme`.
+const REGISTER_HISTORY_LINES: usize = 10;
+
+/// Roster entries not seen for this long are re-read with `getChatMember`, one per tick.
+const USER_REFRESH: chrono::Duration = chrono::Duration::hours(24);
+
+/// Keeps one chat from piling up scheduled calls.
+const MAX_SCHEDULES_PER_CHAT: usize = 20;
+
+/// Updates the handler reacts to. Telegram leaves out member and reaction updates unless asked,
+/// so both polling and `setWebhook` request this list.
+pub const ALLOWED_UPDATES: &[AllowedUpdate] = &[
+    AllowedUpdate::Message,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::ChatMember,
+    AllowedUpdate::MyChatMember,
+    AllowedUpdate::MessageReaction,
+];
+
 pub struct Bot(Arc<Mutex<BotInner>>);
 
 impl Clone for Bot {
@@ -25,45 +64,336 @@ impl Clone for Bot {
 }
 
 impl Bot {
-    pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(BotInner::new())))
+    pub fn new(config: Config) -> anyhow::Result<Self> {
+        let inner = BotInner::new(config, Arc::new(SystemClock))?;
+        Ok(Self(Arc::new_cyclic(|this| {
+            Mutex::new(BotInner {
+                this: this.clone(),
+                ..inner
+            })
+        })))
     }
 
     pub async fn run_active(&self) -> anyhow::Result<()> {
-        let bot_instance = {
-            let inner = self.0.lock().await;
-            inner.bot.clone()
+        let (bot_instance, webhook_config, monitoring_config, storage, reporter) = {
+            let mut inner = self.0.lock().await;
+            inner.resume_pending();
+            if let Err(e) = inner.register_commands().await {
+                tracing::warn!("failed to register bot commands: {:#}", e);
+            }
+            (
+                inner.bot.clone(),
+                inner.config.webhook.clone(),
+                inner.config.monitoring.clone(),
+                inner.storage.clone(),
+                inner.reporter.clone(),
+            )
         };
 
-        let handler = dptree::entry().branch(Update::filter_message().endpoint({
-            let bot = self.clone();
+        self.spawn_scheduler();
 
-            move |_: teloxide::Bot, msg: Message, me: Me| {
-                let bot = bot.clone();
+        let probe = Probe::new(
+            self.clone(),
+            storage,
+            monitoring_config.stale_after_secs.map(Duration::from_secs),
+        );
 
-                async move { bot.0.lock().await.handle_command(msg, me).await }
-            }
-        }));
+        if let Some(address) = monitoring_config.address {
+            let router = crate::metrics::router().merge(health::router(probe.clone()));
+            crate::monitoring::serve(address, router).await?;
+        }
+
+        let handler = dptree::entry()
+            .inspect(|update: Update| {
+                METRICS.update(&update.kind);
+                HEALTH.update_received();
+            })
+            .branch(Update::filter_message().endpoint({
+                let bot = self.clone();
+
+                move |_: teloxide::Bot, msg: Message, me: Me| {
+                    let bot = bot.clone();
+                    let span = tracing::info_span!(
+                        "update",
+                        chat_id = msg.chat.id.0,
+                        user_id = msg.from.as_ref().map(|user| user.id.0),
+                        command = tracing::field::Empty,
+                        outcome = tracing::field::Empty,
+                    );
+
+                    async move {
+                        let chat_id = msg.chat.id;
+                        let (result, command, reporter) = {
+                            let mut inner = bot.0.lock().await;
+                            let result = inner.handle_command(msg, me).await;
+                            inner.current_thread = None;
+                            (result, inner.current_command.take(), inner.reporter.clone())
+                        };
+
+                        record_outcome(&result, command, chat_id, reporter).await;
+                        result
+                    }
+                    .instrument(span)
+                }
+            }))
+            .branch(Update::filter_callback_query().endpoint({
+                let bot = self.clone();
+
+                move |q: CallbackQuery| {
+                    let bot = bot.clone();
+                    let span = tracing::info_span!(
+                        "update",
+                        chat_id = q.message.as_ref().map(|msg| msg.chat().id.0),
+                        user_id = q.from.id.0,
+                        command = tracing::field::Empty,
+                        outcome = tracing::field::Empty,
+                    );
+
+                    async move {
+                        let (chat_id, result, command, reporter) = {
+                            let mut inner = bot.0.lock().await;
+                            let chat_id = q
+                                .message
+                                .as_ref()
+                                .map(|msg| msg.chat().id)
+                                .unwrap_or_else(|| inner.config.white_group());
+                            let result = inner.handle_callback(q).await;
+                            inner.current_thread = None;
+                            let command = inner.current_command.take();
+                            (chat_id, result, command, inner.reporter.clone())
+                        };
+
+                        record_outcome(&result, command, chat_id, reporter).await;
+                        result
+                    }
+                    .instrument(span)
+                }
+            }))
+            .branch(Update::filter_chat_member().endpoint({
+                let bot = self.clone();
+
+                move |update: ChatMemberUpdated| {
+                    let bot = bot.clone();
+                    async move {
+                        if !update.new_chat_member.is_present() {
+                            let user = &update.new_chat_member.user;
+                            bot.0.lock().await.member_left(update.chat.id, user);
+                        }
+                        anyhow::Ok(())
+                    }
+                }
+            }))
+            .branch(Update::filter_my_chat_member().endpoint({
+                let bot = self.clone();
+
+                move |update: ChatMemberUpdated| {
+                    let bot = bot.clone();
+                    async move {
+                        if !update.new_chat_member.is_present() {
+                            bot.0.lock().await.bot_removed(update.chat.id);
+                        }
+                        anyhow::Ok(())
+                    }
+                }
+            }))
+            .branch(Update::filter_message_reaction_updated().endpoint({
+                let bot = self.clone();
+
+                move |update: MessageReactionUpdated| {
+                    let bot = bot.clone();
+                    async move { bot.0.lock().await.handle_reaction(update).await }
+                }
+            }));
 
         tracing::info!("Bot is running...");
 
-        Dispatcher::builder(bot_instance, handler)
-            .build()
-            .dispatch()
-            .await;
+        let mut dispatcher = Dispatcher::builder(bot_instance.clone(), handler).build();
+
+        let shutdown_token = dispatcher.shutdown_token();
+        let systemd = monitoring_config.systemd;
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down, waiting for in-flight updates...");
+
+            HEALTH.set_ready(false);
+            if systemd {
+                health::notify(NotifyState::Stopping);
+            }
+
+            match shutdown_token.shutdown() {
+                Ok(drained) => drained.await,
+                Err(e) => tracing::warn!("failed to stop dispatcher: {}", e),
+            }
+        });
+
+        // Set up before announcing the start, a webhook that can't listen fails startup instead.
+        let webhook = match webhook_config {
+            Some(webhook_config) => {
+                Some(crate::webhook::listener(bot_instance.clone(), &webhook_config).await?)
+            }
+            None => None,
+        };
+
+        if let Some(reporter) = &reporter {
+            reporter
+                .notice(&format!("Bot started, version {}", env!("CARGO_PKG_VERSION")))
+                .await;
+        }
+
+        HEALTH.set_ready(true);
+        if systemd {
+            health::notify(NotifyState::Ready);
+            health::spawn_watchdog(probe);
+        }
+
+        if let Some(listener) = webhook {
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("An error from the webhook listener"),
+                )
+                .await;
+        } else {
+            let polling = Polling::builder(bot_instance)
+                .timeout(Duration::from_secs(10))
+                .delete_webhook()
+                .await
+                .allowed_updates(ALLOWED_UPDATES.to_vec())
+                .build();
+            dispatcher
+                .dispatch_with_listener(
+                    polling,
+                    LoggingErrorHandler::with_custom_text("An error from the update listener"),
+                )
+                .await;
+        }
+
+        self.0.lock().await.shutdown().await;
+
+        tracing::info!("Bot is stopped");
+        Ok(())
+    }
+
+    /// Fires scheduled calls and ends lobby ready checks once they are due, resumed ones
+    /// included.
+    fn spawn_scheduler(&self) {
+        let bot = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(TICK);
+            loop {
+                ticker.tick().await;
+                let mut inner = bot.0.lock().await;
+                inner.apply_reported_migrations().await;
+                inner.fire_due_schedules().await;
+                inner.refresh_stale_user().await;
+                inner.expire_lobbies().await;
+                inner.escalate_calls().await;
+                inner.expire_calls();
+            }
+        });
+    }
+
+    /// Whether the bot state can be locked within `timeout`, i.e. no handler is stuck on it.
+    pub async fn is_responsive(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.0.lock()).await.is_ok()
+    }
+}
+
+/// Records the handler's outcome on the update span and reports errors to the admin chat.
+async fn record_outcome(
+    result: &anyhow::Result<()>, command: Option<&'static str>, chat_id: ChatId,
+    reporter: Option<Arc<ErrorReporter>>,
+) {
+    let span = tracing::Span::current();
+    match result {
+        Ok(()) => {
+            span.record("outcome", "ok");
+        }
+        Err(e) => {
+            span.record("outcome", "error");
+            tracing::warn!("handler failed: {:#}", e);
+            if let Some(reporter) = reporter {
+                reporter.report(command, chat_id, e).await;
+            }
+        }
+    }
+}
+
+/// The forum topic a message was sent in, `None` outside forums and in the General topic.
+fn topic_of(msg: &Message) -> Option<ThreadId> {
+    msg.thread_id.filter(|_| msg.is_topic_message)
+}
+
+fn rsvp_key(rsvp: Rsvp) -> &'static str {
+    match rsvp {
+        Rsvp::Coming => "rsvp-coming",
+        Rsvp::NotComing => "rsvp-not-coming",
+        Rsvp::Later => "rsvp-later",
+    }
+}
 
-        Err(anyhow::anyhow!("Bot is stopped"))
+/// `c 来打牌` calls with a reason, a bare `c` is matched separately.
+fn short_call_reason(text: &str) -> Option<&str> {
+    let reason = text
+        .strip_prefix("c ")
+        .or_else(|| text.strip_prefix("C "))?
+        .trim();
+    (!reason.is_empty()).then_some(reason)
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
     }
 }
 
 struct BotInner {
     bot: teloxide::Bot,
+    config: Config,
+    storage: Storage,
     callmap: CallMap,
+    templates: Templates,
+    deletions: DeletionQueue,
+    schedules: Schedules,
+    lobbies: Lobbies,
+    history: History,
+    active_calls: ActiveCalls,
+    subscribers: Subscribers,
+    /// Groups that became supergroups, oldest first, replayed onto the config on startup.
+    migrations: Vec<(ChatId, ChatId)>,
+    clock: Arc<dyn Clock>,
+    reporter: Option<Arc<ErrorReporter>>,
+    started_at: DateTime<Utc>,
+    /// Kept across `/status` requests, process CPU usage is measured between two refreshes.
+    system: System,
+    /// Command of the update being handled, for error reports.
+    current_command: Option<&'static str>,
+    /// Forum topic of the update being handled, where replies and calls are sent.
+    current_thread: Option<ThreadId>,
+    /// Restored from storage, handed to `deletions` once the runtime is up.
+    restored_deletions: Vec<crate::deletion::PendingDeletion>,
+    /// The state itself, for tasks that come back to it later. Dangling outside of [`Bot`].
+    this: Weak<Mutex<BotInner>>,
 }
 
 type SendMessage = JsonRequest<payloads::SendMessage>;
 
 trait SendMessageExt {
+    async fn send_timed(self) -> Result<Message, RequestError>;
+
     async fn remove_later_30s(
         self, inner: &BotInner, from_msg: MessageId,
     ) -> anyhow::Result<Message>;
@@ -74,23 +404,47 @@ trait SendMessageExt {
 }
 
 impl SendMessageExt for SendMessage {
+    /// Retries once in the supergroup when the group turns out to be migrated.
+    async fn send_timed(mut self) -> Result<Message, RequestError> {
+        let timer = METRICS.send_latency.start_timer();
+        let mut result = self.send_ref().await;
+
+        if let Err(RequestError::MigrateToChatId(to)) = &result
+            && let Recipient::Id(from) = self.payload_ref().chat_id.clone()
+        {
+            let to = *to;
+            tracing::warn!(
+                from = from.0,
+                to = to.0,
+                "group migrated, retrying in the supergroup"
+            );
+            migration::report(from, to);
+            // The replied-to message and the topic belong to the old group.
+            let payload = self.payload_mut();
+            payload.chat_id = Recipient::Id(to);
+            payload.reply_parameters = None;
+            payload.message_thread_id = None;
+            result = self.send_ref().await;
+        }
+        timer.observe_duration();
+
+        if let Err(e) = &result {
+            METRICS.api_error(e);
+        }
+        result
+    }
+
     async fn remove_later_30s(
         self, inner: &BotInner, from_msg_id: MessageId,
     ) -> anyhow::Result<Message> {
-        let bot = inner.bot.clone();
-
-        let sent = self.send().await?;
+        let sent = self.send_timed().await?;
 
-        let recipient: Recipient = sent.chat.id.into();
-        let msg_id = sent.id;
-
-        tokio::spawn(async move {
-            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
-            let _ = bot
-                .delete_messages(recipient, vec![msg_id, from_msg_id])
-                .send()
-                .await;
-        });
+        inner.deletions.schedule(
+            &inner.bot,
+            sent.chat.id,
+            vec![sent.id, from_msg_id],
+            Duration::from_secs(30),
+        );
 
         Ok(sent)
     }
@@ -99,34 +453,51 @@ impl SendMessageExt for SendMessage {
         self, inner: &BotInner, from_msg: Message,
     ) -> anyhow::Result<Message> {
         let bot = inner.bot.clone();
+        let deletions = inner.deletions.clone();
+        let this = inner.this.clone();
 
-        let sent = self.send().await?;
+        let sent = self.send_timed().await?;
 
-        let recipient: Recipient = sent.chat.id.into();
-        let msg_id = sent.id;
-        let from_msg_id = from_msg.id;
-        let Some(from_usr) = from_msg.from else {
+        let chat_id = sent.chat.id;
+        let thread = topic_of(&from_msg);
+        let Some(ref from_usr) = from_msg.from else {
             return Ok(sent);
         };
+        let user_id = from_usr.id;
+        let timeout_hint = inner.text(
+            inner.text_ctx(&from_msg),
+            "captcha-timeout",
+            Args::new().user(from_usr),
+        );
+
+        deletions.schedule(&bot, chat_id, vec![sent.id, from_msg.id], Duration::from_secs(30));
 
         tokio::spawn(async move {
-            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
-            let _ = bot
-                .delete_messages(recipient.clone(), vec![msg_id, from_msg_id])
-                .send()
-                .await;
-            let Ok(r) = bot
-                .send_message(
-                    recipient.clone(),
-                    "#User# 验证超时了捏".replace_user(from_usr),
-                )
-                .send()
-                .await
-            else {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            // Nothing to say when the captcha was answered in time.
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+            let timed_out = {
+                let mut inner = this.lock().await;
+                let now = inner.clock.now();
+                inner.callmap.expire_captcha(chat_id, user_id, now)
+            };
+            if !timed_out {
+                return;
+            }
+            METRICS.captcha("timed_out");
+
+            let mut request = bot
+                .send_message(chat_id, timeout_hint)
+                .parse_mode(teloxide::types::ParseMode::Html);
+            if let Some(thread) = thread {
+                request = request.message_thread_id(thread);
+            }
+            let Ok(r) = request.send_timed().await else {
                 return;
             };
-            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
-            let _ = bot.delete_message(r.chat.id, r.id).send().await;
+            deletions.schedule(&bot, r.chat.id, vec![r.id], Duration::from_secs(30));
         });
 
         Ok(sent)
@@ -134,44 +505,245 @@ impl SendMessageExt for SendMessage {
 }
 
 impl BotInner {
-    pub fn new() -> Self {
-        let http_client = reqwest::Client::builder()
-            .https_only(true)
-            .http2_adaptive_window(true)
-            .build()
-            .expect("failed to build http client");
+    pub fn new(mut config: Config, clock: Arc<dyn Clock>) -> anyhow::Result<Self> {
+        let api_url = config.api.parsed_url()?;
+
+        // A self-hosted Bot API server usually listens on plain HTTP, so only insist on
+        // HTTPS when talking to an HTTPS endpoint.
+        let https_only = api_url.as_ref().is_none_or(|url| url.scheme() == "https");
+
+        let mut http_client = reqwest::Client::builder()
+            .https_only(https_only)
+            .http2_adaptive_window(true);
+
+        if let Some(proxy) = &config.api.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .with_context(|| format!("invalid proxy url: {}", proxy))?;
+            http_client = http_client.proxy(proxy);
+        }
+
+        let http_client = http_client.build().context("failed to build http client")?;
+
+        let mut bot = teloxide::Bot::with_client(crate::BOT_TOKEN, http_client);
+        if let Some(api_url) = api_url {
+            bot = bot.set_api_url(api_url);
+        }
 
-        let bot = teloxide::Bot::with_client(crate::BOT_TOKEN, http_client);
+        let storage = Storage::new(config.storage.path.as_deref())?;
+        let state = storage.load()?;
 
-        Self {
+        for &(from, to) in &state.migrations {
+            if config.migrate_chat(from, to) {
+                tracing::warn!(
+                    from = from.0,
+                    to = to.0,
+                    "the config file still names a migrated group, replace its id with the new one"
+                );
+            }
+        }
+
+        let templates = Templates::load(&config.templates)?;
+
+        let reporter = ErrorReporter::new(bot.clone(), &config.admin).map(Arc::new);
+
+        let mut system = System::new();
+        refresh_process(&mut system);
+
+        Ok(Self {
             bot,
-            callmap: CallMap::new(),
+            config,
+            storage,
+            callmap: state.callmap,
+            templates,
+            deletions: DeletionQueue::new(clock.clone()),
+            schedules: state.schedules,
+            lobbies: state.lobbies,
+            history: state.history,
+            active_calls: state.active_calls,
+            subscribers: state.subscribers,
+            migrations: state.migrations,
+            started_at: clock.now(),
+            clock,
+            reporter,
+            system,
+            current_command: None,
+            current_thread: None,
+            restored_deletions: state.pending_deletions,
+            this: Weak::new(),
+        })
+    }
+
+    fn resume_pending(&mut self) {
+        let pending = std::mem::take(&mut self.restored_deletions);
+        self.deletions.resume(&self.bot, pending);
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        let state = State {
+            callmap: self.callmap.clone(),
+            pending_deletions: self.deletions.pending(),
+            schedules: self.schedules.clone(),
+            lobbies: self.lobbies.clone(),
+            history: self.history.clone(),
+            active_calls: self.active_calls.clone(),
+            subscribers: self.subscribers.clone(),
+            migrations: self.migrations.clone(),
+        };
+
+        self.storage.save(&state)
+    }
+
+    /// Saves right away, for changes that shouldn't be lost if the bot crashes before shutdown.
+    fn save(&self) {
+        if let Err(e) = self.flush() {
+            tracing::warn!("failed to save state: {:#}", e);
+        }
+    }
+
+    async fn shutdown(&mut self) {
+        if let Some(reporter) = &self.reporter {
+            reporter.notice("Bot is shutting down").await;
+        }
+
+        if let Some(notice) = &self.config.shutdown.notice
+            && let Err(e) = self.send_message(self.config.white_group(), notice.clone()).await
+        {
+            tracing::warn!("failed to post shutdown notice: {}", e);
+        }
+
+        match self.flush() {
+            Ok(()) => tracing::info!("State saved to {}", self.storage.path().display()),
+            Err(e) => tracing::error!("failed to save state: {:#}", e),
+        }
+    }
+
+    /// The chat's configured locale wins, then the sender's language, then the default.
+    fn locale(&self, msg: &Message) -> Locale {
+        self.locale_for(msg.chat.id, msg.from.as_ref())
+    }
+
+    fn locale_for(&self, chat_id: ChatId, user: Option<&User>) -> Locale {
+        let config = &self.config.i18n;
+
+        if let Some(locale) = config.chats.get(&chat_id.to_string()) {
+            return *locale;
+        }
+
+        if config.per_user
+            && let Some(locale) = user
+                .and_then(|user| user.language_code.as_deref())
+                .and_then(Locale::from_language_code)
+        {
+            return locale;
         }
+
+        config.default
+    }
+
+    fn text_ctx(&self, msg: &Message) -> TextCtx {
+        TextCtx {
+            locale: self.locale(msg),
+            chat_id: msg.chat.id,
+        }
+    }
+
+    /// For messages not triggered by anyone, e.g. scheduled calls.
+    fn chat_text_ctx(&self, chat_id: ChatId) -> TextCtx {
+        TextCtx {
+            locale: self.locale_for(chat_id, None),
+            chat_id,
+        }
+    }
+
+    fn text(&self, ctx: TextCtx, key: &str, args: Args) -> String {
+        self.templates.render(ctx, key, args)
     }
 
+    /// Every text the bot sends is Telegram HTML, see [`crate::template::Template`].
     fn send_message<C, T>(&self, chat_id: C, text: T) -> SendMessage
     where
         C: Into<Recipient>,
         T: Into<String>,
     {
-        self.bot.send_message(chat_id, text)
+        let request = self
+            .bot
+            .send_message(chat_id, text)
+            .parse_mode(teloxide::types::ParseMode::Html);
+
+        match self.current_thread {
+            Some(thread) => request.message_thread_id(thread),
+            None => request,
+        }
+    }
+
+    /// Direct messages never go to a topic, unlike [`Self::send_message`].
+    fn send_private<T>(&self, user_id: UserId, text: T) -> SendMessage
+    where
+        T: Into<String>,
+    {
+        self.bot
+            .send_message(user_id, text)
+            .parse_mode(teloxide::types::ParseMode::Html)
+    }
+
+    /// The topic whose roster commands work on, `None` for the group's shared one.
+    fn roster_topic(&self) -> Option<ThreadId> {
+        self.current_thread
+            .filter(|_| self.config.topics.separate_rosters)
     }
 
     async fn handle_command(&mut self, msg: Message, me: Me) -> anyhow::Result<()> {
         tracing::debug!("Received message: {:?}", msg);
+        self.current_command = None;
+        self.current_thread = topic_of(&msg);
+        self.apply_reported_migrations().await;
+
+        // Sent in the old group and in the new supergroup, whichever arrives first moves the state.
+        if let Some(&to) = msg.migrate_to_chat_id() {
+            self.migrate_chat(msg.chat.id, to).await;
+            return Ok(());
+        }
+        if let Some(&from) = msg.migrate_from_chat_id() {
+            self.migrate_chat(from, msg.chat.id).await;
+            return Ok(());
+        }
 
         if msg.from.is_none() {
             return Ok(());
         };
 
-        if msg.chat_id() != Some(ChatId(crate::WHITE_GROUP)) {
-            self.send_message(msg.chat.id, "请在 P游戏部 群内使用此机器人")
+        // Usually also delivered as a chat member update, handling both is harmless.
+        if let Some(user) = msg.left_chat_member() {
+            if user.id == me.id {
+                self.bot_removed(msg.chat.id);
+            } else {
+                self.member_left(msg.chat.id, user);
+            }
+            return Ok(());
+        }
+
+        if msg.chat.is_private() {
+            return self.handle_private(msg, me).await;
+        }
+
+        if msg.chat_id() != Some(self.config.white_group()) {
+            let t = self.text_ctx(&msg);
+            self.send_message(msg.chat.id, self.text(t, "not-white-group", Args::new()))
                 .remove_later_30s(self, msg.id)
                 .await?;
 
             return Ok(());
         }
 
+        if let Some(from) = &msg.from {
+            let refreshed = self.refresh_user(msg.chat.id, from, self.clock.now());
+            // Any message answers the calls the sender was pinged in.
+            let responded = self.active_calls.respond(msg.chat.id, from.id);
+            if refreshed || responded {
+                self.save();
+            }
+        }
+
         if let Some(cmd) = msg
             .text()
             .and_then(|msg| cmd::Command::parse(msg, me.username()).ok())
@@ -184,11 +756,47 @@ impl BotInner {
         Ok(())
     }
 
+    /// Only the notification settings and help work in the private chat, everything else is for
+    /// the group.
+    async fn handle_private(&mut self, msg: Message, me: Me) -> anyhow::Result<()> {
+        let cmd = msg
+            .text()
+            .and_then(|text| Command::parse(text, me.username()).ok());
+
+        match cmd {
+            Some(cmd @ (Command::Start | Command::Notify(_) | Command::Help(_))) => {
+                self.handle_command_inner(msg, cmd).await
+            }
+            _ => {
+                let t = self.text_ctx(&msg);
+                self.send_message(msg.chat.id, self.text(t, "not-white-group", Args::new()))
+                    .remove_later_30s(self, msg.id)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
     async fn handle_message(&mut self, msg: Message) -> anyhow::Result<()> {
+        if let Some(reason) = msg.text().and_then(short_call_reason) {
+            let reason = reason.to_string();
+            self.record_command(Command::CallPU(String::new()).name(), "short");
+            return self.call_pu(msg, Some(reason)).await;
+        }
+
         match msg.text() {
-            Some("r") | Some("R") => self.register_user(msg).await?,
-            Some("l") | Some("L") | Some("丨") => self.leave_user(msg).await?,
-            Some("c") | Some("C") => self.call_pu(msg).await?,
+            Some("r") | Some("R") => {
+                self.record_command(Command::Register.name(), "short");
+                self.register_user(msg, RegisterVia::Shortcut).await?
+            }
+            Some("l") | Some("L") | Some("丨") => {
+                self.record_command(Command::Leave.name(), "short");
+                self.leave_user(msg).await?
+            }
+            Some("c") | Some("C") => {
+                self.record_command(Command::CallPU(String::new()).name(), "short");
+                self.call_pu(msg, None).await?
+            }
             Some("true") | Some("True") | Some("TRUE") | Some("t") | Some("y") => {
                 self.answer_captcha(&msg, true).await?
             }
@@ -201,61 +809,97 @@ impl BotInner {
         Ok(())
     }
 
+    fn record_command(&mut self, name: &'static str, via: &'static str) {
+        self.current_command = Some(name);
+        METRICS.command(name, via);
+        tracing::Span::current().record("command", name);
+    }
+
     async fn handle_command_inner(&mut self, msg: Message, cmd: Command) -> anyhow::Result<()> {
+        self.record_command(cmd.name(), "command");
+
         match cmd {
-            Command::Help => self.handle_help_request(msg).await,
-            Command::CallPU => self.call_pu(msg).await,
-            Command::Register => self.register_user(msg).await,
+            Command::Start => self.set_notify(msg, "on").await,
+            Command::Help(topic) => self.handle_help_request(msg, &topic).await,
+            Command::CallPU(reason) => {
+                let reason = Some(reason.trim().to_string()).filter(|reason| !reason.is_empty());
+                self.call_pu(msg, reason).await
+            }
+            Command::CancelCall => self.end_call(msg, CallEnd::Cancelled).await,
+            Command::CloseCall => self.end_call(msg, CallEnd::Closed).await,
+            Command::CallAt(args) => self.schedule_call(msg, &args).await,
+            Command::Schedules => self.list_schedules(msg).await,
+            Command::Unschedule(id) => self.unschedule_call(msg, &id).await,
+            Command::Lobby(args) => self.open_lobby(msg, &args).await,
+            Command::Teams(teams) => self.split_teams(msg, &teams).await,
+            Command::Pick(reason) => self.pick_player(msg, &reason).await,
+            Command::Order => self.shuffle_order(msg).await,
+            Command::Stats => self.show_stats(msg).await,
+            Command::Register => self.register_user(msg, RegisterVia::Command).await,
             Command::Leave => self.leave_user(msg).await,
             Command::WhoRegisteredMe => self.who_registered_me(msg).await,
+            Command::Notify(arg) => self.set_notify(msg, &arg).await,
             Command::Blacklist => self.captcha_blacklist_user(msg).await,
             Command::Unblacklist => self.unblacklist_user(msg).await,
+            Command::Status => self.handle_status_request(msg).await,
         }
     }
 
     async fn blacklist_user(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let chat_id = msg.chat.id;
         let Some(ref from_user) = msg.from else {
             return Ok(());
         };
 
-        if let BlacklistResult::AlreadyBlacklisted = self.callmap.blacklist(chat_id, from_user.id) {
-            self.send_message(
-                msg.chat.id,
-                "#User# 你已经在 Call 黑名单里了捏".replace_user(from_user.clone()),
-            )
-            .parse_mode(teloxide::types::ParseMode::Html)
-            .remove_later_30s(self, msg.id)
-            .await?;
-            return Ok(());
-        }
+        let left = match self
+            .callmap
+            .blacklist(chat_id, from_user.id, self.clock.now())
+        {
+            BlacklistResult::AlreadyBlacklisted => {
+                self.send_message(
+                    msg.chat.id,
+                    self.text(t, "blacklist-already", Args::new().user(from_user)),
+                )
+                .remove_later_30s(self, msg.id)
+                .await?;
+                return Ok(());
+            }
+            BlacklistResult::Blacklisted { left } => left,
+        };
+        self.save();
 
         self.send_message(
             msg.chat.id,
-            "#User# 已加入 Call 黑名单".replace_user(from_user.clone()),
+            self.text(t, "blacklist-done", Args::new().user(from_user)),
         )
-        .parse_mode(teloxide::types::ParseMode::Html)
         .remove_later_30s(self, msg.id)
         .await?;
 
-        if self.callmap.has_user(&chat_id, from_user) {
-            self.leave_user(msg).await?;
+        if left {
+            self.send_message(
+                msg.chat.id,
+                self.text(t, "leave-done", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
         }
 
         Ok(())
     }
 
     async fn captcha_blacklist_user(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let Some(ref from_user) = msg.from else {
             return Ok(());
         };
-        
+
+        self.expire_captchas(msg.chat.id);
         if self.callmap.has_captcha(&msg.chat.id, &from_user.id) {
             self.send_message(
                 msg.chat.id,
-                "#User# 你已经有一个未完成的人机验证了捏".replace_user(msg.from.as_ref().unwrap().clone()),
+                self.text(t, "captcha-pending", Args::new().user(from_user)),
             )
-            .parse_mode(teloxide::types::ParseMode::Html)
             .remove_later_30s(self, msg.id)
             .await?;
             return Ok(());
@@ -272,20 +916,33 @@ impl BotInner {
         Ok(())
     }
 
+    fn expire_captchas(&mut self, chat_id: ChatId) {
+        let expired = self.callmap.expire_captchas(chat_id, self.clock.now());
+        if expired > 0 {
+            METRICS.captchas.with_label_values(&["timed_out"]).inc_by(expired as u64);
+        }
+    }
+
     async fn captcha_user(&mut self, msg: &Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(msg);
         let Some(ref from_user) = msg.from else {
             return Ok(());
         };
 
-        let captcha_msg = QUESTION_HINT.replace_user(from_user.clone());
+        let captcha_msg = self.text(t, "captcha-hint", Args::new().user(from_user));
         let Some(captcha_question) = QUESTION_MAP
             .get(rand::random::<u64>() as usize % QUESTION_MAP.len())
         else {
             return Ok(());
         };
 
-        self.callmap
-            .push_captcha(msg.chat.id, from_user.id, captcha_question.1);
+        self.callmap.push_captcha(
+            msg.chat.id,
+            from_user.id,
+            captcha_question.1,
+            self.clock.now(),
+        );
+        METRICS.captcha("issued");
 
         self.send_message(
             msg.chat.id,
@@ -295,7 +952,6 @@ impl BotInner {
                 teloxide::utils::html::code_block_with_lang(captcha_question.0, "Rust")
             ),
         )
-        .parse_mode(teloxide::types::ParseMode::Html)
         .remove_later_30s_with_timeout_hint(self, msg.clone())
         .await?;
 
@@ -303,31 +959,37 @@ impl BotInner {
     }
 
     async fn answer_captcha(&mut self, msg: &Message, ans: bool) -> anyhow::Result<()> {
+        let t = self.text_ctx(msg);
         let Some(ref from_user) = msg.from else {
             return Ok(());
         };
 
-        let Some(expected_answer) = self.callmap.pop_captcha(msg.chat.id, &from_user.id) else {
+        self.expire_captchas(msg.chat.id);
+        let now = self.clock.now();
+        let Some(expected_answer) = self.callmap.pop_captcha(msg.chat.id, &from_user.id, now)
+        else {
             return Ok(());
         };
 
         if ans != expected_answer {
+            METRICS.captcha("failed");
             self.send_message(
                 msg.chat.id,
-                "#User# 人机验证失败，未加入 Call 黑名单".replace_user(from_user.clone()),
+                self.text(t, "captcha-failed", Args::new().user(from_user)),
             )
-            .parse_mode(teloxide::types::ParseMode::Html)
             .remove_later_30s(self, msg.id)
             .await?;
             return Ok(());
         }
 
+        METRICS.captcha("passed");
         self.blacklist_user(msg.clone()).await?;
 
         Ok(())
     }
 
     async fn unblacklist_user(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let chat_id = msg.chat.id;
         let Some(from_user) = msg.from else {
             return Ok(());
@@ -336,19 +998,18 @@ impl BotInner {
         if let UnblacklistResult::NotInBlacklist = self.callmap.unblacklist(chat_id, from_user.id) {
             self.send_message(
                 msg.chat.id,
-                "#User# 你不在 Call 黑名单里捏".replace_user(from_user),
+                self.text(t, "unblacklist-not-in", Args::new().user(&from_user)),
             )
-            .parse_mode(teloxide::types::ParseMode::Html)
             .remove_later_30s(self, msg.id)
             .await?;
             return Ok(());
         }
+        self.save();
 
         self.send_message(
             msg.chat.id,
-            "#User# 已从 Call 黑名单移除".replace_user(from_user),
+            self.text(t, "unblacklist-done", Args::new().user(&from_user)),
         )
-        .parse_mode(teloxide::types::ParseMode::Html)
         .remove_later_30s(self, msg.id)
         .await?;
 
@@ -356,90 +1017,1329 @@ impl BotInner {
     }
 
     async fn who_registered_me(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let chat_id = msg.chat.id;
         let Some(from_user) = msg.from else {
             return Ok(());
         };
 
-        if !self.callmap.has_user(&chat_id, &from_user) {
+        let topic = self.roster_topic();
+        if !self.callmap.has_user(&chat_id, topic, &from_user) {
             self.send_message(
                 msg.chat.id,
-                "#User# 还没有人注册你捏".replace_user(from_user),
+                self.text(t, "whoregistered-none", Args::new().user(&from_user)),
             )
-            .parse_mode(teloxide::types::ParseMode::Html)
             .remove_later_30s(self, msg.id)
             .await?;
             return Ok(());
         }
 
-        if let Some(registered_by) = self.callmap.get_register(&chat_id ,from_user) {
+        let history = self.register_history(t, chat_id, from_user.id);
+
+        if let Some(registered_by) = self.callmap.get_register(&chat_id, topic, from_user.clone()) {
             self.send_message(
                 msg.chat.id,
-                "查到了！#User# 注册了你捏".replace_user(registered_by.clone()),
+                self.text(
+                    t,
+                    "whoregistered-found",
+                    Args::new()
+                        .user(&from_user)
+                        .registrar(&registered_by)
+                        .history(&history),
+                ),
             )
-            .parse_mode(teloxide::types::ParseMode::Html)
             .remove_later_30s(self, msg.id)
             .await?;
         } else {
-            self.send_message(msg.chat.id, "10% 的几率！ Bot 忘了捏")
-                .remove_later_30s(self, msg.id)
-                .await?;
+            self.send_message(
+                msg.chat.id,
+                self.text(
+                    t,
+                    "whoregistered-forgot",
+                    Args::new().user(&from_user).history(&history),
+                ),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
         }
 
         Ok(())
     }
 
-    async fn call_pu(&mut self, msg: Message) -> anyhow::Result<()> {
-        let chat_id = msg.chat.id;
-        let Some(from_user) = msg.from else {
-            return Ok(());
-        };
-
-        let call_list = self.callmap.get_call_list(chat_id);
+    /// Takes a user who left or was removed from the group off the roster.
+    fn member_left(&mut self, chat_id: ChatId, user: &User) {
+        let keep_blacklist = self.config.register.keep_blacklist;
+        let now = self.clock.now();
+        if self
+            .callmap
+            .remove_member(chat_id, user.id, keep_blacklist, now)
+        {
+            tracing::info!(
+                chat_id = chat_id.0,
+                user_id = user.id.0,
+                "removed departed user from roster"
+            );
+            self.save();
+        }
+    }
 
-        if call_list.is_empty() {
-            self.send_message(msg.chat.id, "没有人捏，你来 r 一下吧")
-                .remove_later_30s(self, msg.id)
-                .await?;
-            return Ok(());
+    /// Moves the state of a group that became a supergroup to its new id. The config file still
+    /// names the old id, so the admins are asked to update it; until then the recorded migration
+    /// is replayed on startup.
+    async fn migrate_chat(&mut self, from: ChatId, to: ChatId) {
+        if from == to || self.migrations.iter().any(|&(old, _)| old == from) {
+            return;
         }
 
-        let is_in_list = call_list.iter().any(|user| user.id == from_user.id);
-        if !is_in_list {
-            self.send_message(msg.chat.id, "你不许参加 impart !")
-                .remove_later_30s(self, msg.id)
-                .await?;
-            return Ok(());
+        self.callmap.migrate_chat(from, to);
+        let schedules = self.schedules.migrate_chat(from, to);
+        // Lobby messages stay behind in the old group, their buttons can't be used anymore.
+        let lobbies = self.lobbies.remove_chat(from);
+        self.history.migrate_chat(from, to);
+        self.active_calls.migrate_chat(from, to);
+        let in_config = self.config.migrate_chat(from, to);
+        self.templates.migrate_chat(from, to);
+        self.migrations.push((from, to));
+        self.save();
+
+        tracing::warn!(
+            from = from.0,
+            to = to.0,
+            schedules,
+            lobbies,
+            "group migrated to a supergroup, moved its state"
+        );
+        if in_config {
+            tracing::warn!(
+                from = from.0,
+                to = to.0,
+                "the config file names the old group id, replace it with the new one"
+            );
+        }
+        if let Some(reporter) = &self.reporter {
+            reporter
+                .notice(&format!(
+                    "Group {} became supergroup {}, its state moved along. Replace the old id in the config file.",
+                    from, to
+                ))
+                .await;
         }
+    }
 
-        let mention_list = call_list
+    /// Applies the migrations [`SendMessageExt::send_timed`] ran into.
+    async fn apply_reported_migrations(&mut self) {
+        for (from, to) in migration::take_reported() {
+            self.migrate_chat(from, to).await;
+        }
+    }
+
+    /// Takes the current names of a user seen in the chat, for the roster as well as the calls,
+    /// schedules, lobbies and history that mention them. Returns whether any name changed.
+    fn refresh_user(&mut self, chat_id: ChatId, user: &User, now: DateTime<Utc>) -> bool {
+        // Names are global, so records outside this chat take them too.
+        let mut changed = self.callmap.refresh_user(chat_id, user, now);
+        changed |= self.active_calls.refresh_user(user);
+        changed |= self.schedules.refresh_user(user);
+        changed |= self.lobbies.refresh_user(user);
+        changed |= self.history.refresh_user(user);
+        changed
+    }
+
+    /// Re-reads the roster entry unchecked the longest, so mentions follow renames of people who
+    /// rarely write in the chat.
+    async fn refresh_stale_user(&mut self) {
+        let now = self.clock.now();
+        let Some((chat_id, user_id)) = self.callmap.stale_user(now, USER_REFRESH) else {
+            return;
+        };
+
+        match self.bot.get_chat_member(chat_id, user_id).await {
+            Ok(member) if !member.is_present() => self.member_left(chat_id, &member.user),
+            Ok(member) => {
+                if self.refresh_user(chat_id, &member.user, now) {
+                    self.save();
+                }
+            }
+            Err(e) => {
+                METRICS.api_error(&e);
+                tracing::debug!("failed to refresh user {} in {}: {}", user_id, chat_id, e);
+                self.callmap.touch_user(chat_id, user_id, now);
+            }
+        }
+    }
+
+    /// Forgets everything about a chat the bot was removed from.
+    fn bot_removed(&mut self, chat_id: ChatId) {
+        // Blocked in a private chat.
+        if let Some(user_id) = chat_id.as_user() {
+            if self.subscribers.unsubscribe(user_id) {
+                tracing::info!(user_id = user_id.0, "blocked by a subscriber, unsubscribed");
+                self.save();
+            }
+            return;
+        }
+
+        let roster = self.callmap.remove_chat(chat_id);
+        let schedules = self.schedules.remove_chat(chat_id);
+        let lobbies = self.lobbies.remove_chat(chat_id);
+        let calls = self.history.remove_chat(chat_id);
+        let active_calls = self.active_calls.remove_chat(chat_id);
+
+        tracing::info!(
+            chat_id = chat_id.0,
+            roster,
+            schedules,
+            lobbies,
+            calls,
+            active_calls,
+            "removed from chat, dropped its state"
+        );
+        self.save();
+    }
+
+    /// The latest registrations and leaves of a user, one per line in the chat's timezone. Names
+    /// are plain text so the history doesn't ping anyone.
+    fn register_history(&self, t: TextCtx, chat_id: ChatId, user_id: UserId) -> String {
+        let tz = self.config.schedule.timezone_for(chat_id);
+        let events = self.callmap.register_history(&chat_id, &user_id);
+
+        events[events.len().saturating_sub(REGISTER_HISTORY_LINES)..]
             .iter()
-            .filter_map(|user: &User| {
-                if from_user.id == user.id {
-                    None
-                } else {
-                    Some("#User#".replace_user(user.clone())).to_owned()
+            .map(|event| {
+                let at = event.at.with_timezone(&tz).format("%Y-%m-%d %H:%M");
+                match &event.action {
+                    RegisterAction::Registered { by, via, anonymous } => {
+                        let by = match by {
+                            Some(by) if by.id == user_id => self.text(t, "audit-self", Args::new()),
+                            Some(by) if !anonymous => html::escape(&by.full_name()),
+                            _ => self.text(t, "audit-anonymous", Args::new()),
+                        };
+                        let via = match via {
+                            RegisterVia::Command => "audit-via-command",
+                            RegisterVia::Shortcut => "audit-via-shortcut",
+                            RegisterVia::Reply => "audit-via-reply",
+                        };
+                        format!(
+                            "{} {} {} ({})",
+                            at,
+                            self.text(t, "audit-registered-by", Args::new()),
+                            by,
+                            self.text(t, via, Args::new())
+                        )
+                    }
+                    RegisterAction::Left => {
+                        format!("{} {}", at, self.text(t, "audit-left", Args::new()))
+                    }
+                    RegisterAction::LeftChat => {
+                        format!("{} {}", at, self.text(t, "audit-left-chat", Args::new()))
+                    }
                 }
             })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Mentions everyone on the roster but the caller, with the `reason` shown on top. When the
+    /// call is a reply, the call message replies to the same message for context.
+    async fn call_pu(&mut self, msg: Message, reason: Option<String>) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let chat_id = msg.chat.id;
+        let reply_to = msg.reply_to_message().map(|reply_to| reply_to.id);
+        let Some(from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let call_list = self.callmap.get_call_list(chat_id, self.roster_topic());
+
+        if call_list.is_empty() {
+            self.send_message(msg.chat.id, self.text(t, "call-empty", Args::new().user(&from_user)))
+                .remove_later_30s(self, msg.id)
+                .await?;
+            return Ok(());
+        }
+
+        let is_in_list = call_list.iter().any(|user| user.id == from_user.id);
+        if !is_in_list {
+            self.send_message(msg.chat.id, self.text(t, "call-not-in-list", Args::new().user(&from_user)))
+                .remove_later_30s(self, msg.id)
+                .await?;
+            return Ok(());
+        }
+
+        let mention_list = call_list
+            .into_iter()
+            .filter(|user: &User| from_user.id != user.id)
+            .collect::<Vec<_>>();
+
+        if mention_list.is_empty() {
+            self.send_message(
+                msg.chat.id,
+                self.text(t, "call-no-others", Args::new().user(&from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        self.send_call(
+            t,
+            chat_id,
+            &from_user,
+            &mention_list,
+            reason.as_deref(),
+            reply_to,
+        )
+        .await
+    }
+
+    async fn send_call(
+        &mut self, t: TextCtx, chat_id: ChatId, caller: &User, mention_list: &[User],
+        reason: Option<&str>, reply_to: Option<MessageId>,
+    ) -> anyhow::Result<()> {
+        let mut args = Args::new()
+            .user(caller)
+            .count(mention_list.len())
+            .roster(mention_list);
+        let key = match reason {
+            Some(reason) => {
+                args = args.reason(reason);
+                "call-message-reason"
+            }
+            None => "call-message",
+        };
+
+        let text = self.text(t, key, args);
+        let mut call_msg = self.send_message(chat_id, text.clone());
+        if let Some(reply_to) = reply_to {
+            call_msg = call_msg.reply_parameters(ReplyParameters::new(reply_to));
+        }
+        let sent = call_msg.send_timed().await?;
+
+        METRICS.calls.inc();
+        METRICS.roster_size.observe(mention_list.len() as f64);
+
+        let now = self.clock.now();
+        if let Some(retention) = self.config.history.retention() {
+            self.history.prune(now, retention);
+        }
+        self.history
+            .record(chat_id, caller, mention_list, reason, Some(sent.id), now);
+
+        let link = sent.url().map(|url| url.to_string());
+        let interval = self.config.escalation.policy_for(chat_id).interval();
+        self.active_calls.add(ActiveCall {
+            chat_id,
+            thread: self.current_thread,
+            message_id: sent.id,
+            text,
+            link: link.clone(),
+            caller: caller.into(),
+            pending: mention_list.iter().map(UserRecord::from).collect(),
+            answers: Vec::new(),
+            sent_at: now,
+            round: 0,
+            next_ping: interval.map(|interval| now + interval),
+        });
+        self.save();
+
+        self.notify_subscribers(t, caller, mention_list, reason, &sent, link.as_deref())
+            .await;
+        Ok(())
+    }
+
+    /// `/cancelcall` and `/closecall`: ends the call replied to, or the sender's latest, and marks
+    /// its message. Only the caller or an admin may end a call.
+    async fn end_call(&mut self, msg: Message, end: CallEnd) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let chat_id = msg.chat.id;
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+        let (none, forbidden, done, footer) = match end {
+            CallEnd::Cancelled => (
+                "cancelcall-none",
+                "cancelcall-forbidden",
+                "cancelcall-done",
+                "call-cancelled",
+            ),
+            CallEnd::Closed => (
+                "closecall-none",
+                "closecall-forbidden",
+                "closecall-done",
+                "call-closed",
+            ),
+        };
+
+        let call = match msg.reply_to_message() {
+            Some(reply_to) => self.active_calls.get(chat_id, reply_to.id),
+            None => self.active_calls.latest_by(chat_id, from_user.id),
+        };
+        let Some(call) = call.cloned() else {
+            self.send_message(chat_id, self.text(t, none, Args::new().user(from_user)))
+                .remove_later_30s(self, msg.id)
+                .await?;
+            return Ok(());
+        };
+
+        if call.caller.id != from_user.id && !self.is_admin(&msg).await? {
+            self.send_message(
+                chat_id,
+                self.text(t, forbidden, Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        self.active_calls.remove(chat_id, call.message_id);
+        self.save();
+
+        let footer = self.text(
+            self.chat_text_ctx(chat_id),
+            footer,
+            Args::new().user(from_user),
+        );
+        self.refresh_call_message(&call, Some(&footer)).await;
+        if let Some(delay) = self.config.calls.delete_ended_after() {
+            self.deletions
+                .schedule(&self.bot, chat_id, vec![call.message_id], delay);
+        }
+
+        self.send_message(chat_id, self.text(t, done, Args::new().user(from_user)))
+            .remove_later_30s(self, msg.id)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Pings again whoever hasn't answered a call in time, while the chat's policy has rounds
+    /// left.
+    async fn escalate_calls(&mut self) {
+        let now = self.clock.now();
+        let due = self.active_calls.take_due(now);
+        if due.is_empty() {
+            return;
+        }
+
+        for mut call in due {
+            let policy = self.config.escalation.policy_for(call.chat_id);
+            // Turned off since the call was made.
+            let Some(interval) = policy.interval() else {
+                call.next_ping = None;
+                self.active_calls.add(call);
+                continue;
+            };
+
+            call.round += 1;
+            self.current_thread = call.thread;
+            if let Err(e) = self.reping(&call, policy).await {
+                tracing::warn!(
+                    chat_id = call.chat_id.0,
+                    "failed to follow up on call {}: {:#}",
+                    call.message_id,
+                    e
+                );
+            }
+
+            call.next_ping = (call.round < policy.rounds).then(|| now + interval);
+            self.active_calls.add(call);
+        }
+        self.current_thread = None;
+
+        self.save();
+    }
+
+    /// Forgets calls too old to be answered.
+    fn expire_calls(&mut self) {
+        if self.active_calls.expire(self.clock.now()) > 0 {
+            self.save();
+        }
+    }
+
+    /// Reactions to a call are answers: they stop its follow-ups for the user, count towards
+    /// `/stats` and show up in the tally under the call.
+    async fn handle_reaction(&mut self, update: MessageReactionUpdated) -> anyhow::Result<()> {
+        let Some(user) = update.user() else {
+            return Ok(());
+        };
+        let chat_id = update.chat.id;
+        let rsvp = update
+            .new_reaction
+            .iter()
+            .filter_map(ReactionType::emoji)
+            .find_map(|emoji| self.config.rsvp.rsvp_for(emoji));
+
+        let Some(call) = self
+            .active_calls
+            .rsvp(chat_id, update.message_id, user, rsvp)
+            .cloned()
+        else {
+            return Ok(());
+        };
+
+        if rsvp.is_some() {
+            self.history.respond(chat_id, update.message_id, user.id);
+        }
+        self.save();
+
+        self.refresh_call_message(&call, None).await;
+        Ok(())
+    }
+
+    /// Rewrites the call message with the current tally of answers below it, and `footer` last.
+    async fn refresh_call_message(&self, call: &ActiveCall, footer: Option<&str>) {
+        let t = self.chat_text_ctx(call.chat_id);
+        let mut text = call.text.clone();
+
+        let tally = Rsvp::ALL
+            .into_iter()
+            .filter_map(|rsvp| {
+                let users = call.answered(rsvp);
+                (!users.is_empty()).then(|| {
+                    let names = users
+                        .iter()
+                        .map(|user| html::escape(&user.full_name()))
+                        .collect::<Vec<_>>();
+                    format!(
+                        "{} ({}): {}",
+                        self.text(t, rsvp_key(rsvp), Args::new()),
+                        users.len(),
+                        names.join(", ")
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        if !tally.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(&tally.join("\n"));
+        }
+        if let Some(footer) = footer {
+            text.push_str("\n\n");
+            text.push_str(footer);
+        }
+
+        let edit = self
+            .bot
+            .edit_message_text(call.chat_id, call.message_id, text)
+            .parse_mode(teloxide::types::ParseMode::Html);
+        if let Err(e) = edit.await {
+            METRICS.api_error(&e);
+            tracing::warn!("failed to update call {}: {}", call.message_id, e);
+        }
+    }
+
+    /// Mentions the call's non-responders in reply to it. From the second round on the
+    /// subscribed ones are also messaged privately when the policy says so.
+    async fn reping(&mut self, call: &ActiveCall, policy: EscalationPolicy) -> anyhow::Result<()> {
+        let t = self.chat_text_ctx(call.chat_id);
+        let caller = call.caller.to_user();
+        let pending = UserRecord::to_users(&call.pending);
+        let args = Args::new()
+            .user(&caller)
+            .count(call.round as usize)
+            .roster(&pending);
+        self.send_message(call.chat_id, self.text(t, "call-reping", args))
+            .reply_parameters(ReplyParameters::new(call.message_id).allow_sending_without_reply())
+            .send_timed()
+            .await?;
+
+        if policy.dm && call.round > 1 {
+            let link = call.link.as_deref().unwrap_or_default();
+            let text = self.text(t, "dm-reping", Args::new().user(&caller).link(link));
+            self.message_subscribers(&pending, text).await;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the call privately to the pinged users who opted in.
+    async fn notify_subscribers(
+        &mut self, t: TextCtx, caller: &User, mention_list: &[User], reason: Option<&str>,
+        call: &Message, link: Option<&str>,
+    ) {
+        let chat = call.chat.title().unwrap_or_default();
+        let mut args = Args::new()
+            .user(caller)
+            .chat(chat)
+            .link(link.unwrap_or_default());
+        let key = match reason {
+            Some(reason) => {
+                args = args.reason(reason);
+                "dm-call-reason"
+            }
+            None => "dm-call",
+        };
+        let text = self.text(t, key, args);
+        self.message_subscribers(mention_list, text).await;
+    }
+
+    /// Sends `text` privately to those of `users` who subscribed, unsubscribing the ones the bot
+    /// can't reach anymore.
+    async fn message_subscribers(&mut self, users: &[User], text: String) {
+        let mut unreachable = Vec::new();
+        for user in users
+            .iter()
+            .filter(|user| self.subscribers.is_subscribed(user.id))
+        {
+            match self.send_private(user.id, text.clone()).send_timed().await {
+                Ok(_) => {}
+                Err(e) if notify::is_unreachable(&e) => {
+                    tracing::info!(
+                        user_id = user.id.0,
+                        "subscriber unreachable, unsubscribing: {}",
+                        e
+                    );
+                    unreachable.push(user.id);
+                }
+                Err(e) => tracing::warn!(user_id = user.id.0, "failed to notify subscriber: {}", e),
+            }
+        }
+
+        if !unreachable.is_empty() {
+            for user_id in unreachable {
+                self.subscribers.unsubscribe(user_id);
+            }
+            self.save();
+        }
+    }
+
+    /// `/start` and `/notify on|off` in the private chat, with no argument shows the setting.
+    async fn set_notify(&mut self, msg: Message, arg: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from) = msg.from else {
+            return Ok(());
+        };
+
+        if !msg.chat.is_private() {
+            self.send_message(
+                msg.chat.id,
+                self.text(t, "notify-private-only", Args::new().user(from)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        let key = match arg.trim().to_lowercase().as_str() {
+            "on" => {
+                self.subscribers.subscribe(from.id);
+                self.save();
+                "notify-on"
+            }
+            "off" => {
+                self.subscribers.unsubscribe(from.id);
+                self.save();
+                "notify-off"
+            }
+            "" if self.subscribers.is_subscribed(from.id) => "notify-status-on",
+            "" => "notify-status-off",
+            _ => "notify-usage",
+        };
+
+        self.send_message(msg.chat.id, self.text(t, key, Args::new().user(from)))
+            .send_timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn schedule_call(&mut self, msg: Message, args: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let chat_id = msg.chat.id;
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let Some(spec) = ScheduleSpec::parse(args) else {
+            self.send_message(
+                chat_id,
+                self.text(t, "callat-usage", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        };
+
+        if !self.callmap.has_user(&chat_id, self.roster_topic(), from_user) {
+            self.send_message(
+                chat_id,
+                self.text(t, "call-not-in-list", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        if self.schedules.for_chat(chat_id).len() >= MAX_SCHEDULES_PER_CHAT {
+            self.send_message(
+                chat_id,
+                self.text(t, "callat-too-many", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        let tz = self.config.schedule.timezone_for(chat_id);
+        let now = self.clock.now();
+        let description = self
+            .schedules
+            .add(chat_id, self.current_thread, from_user, spec, tz, now)
+            .describe(t.locale);
+        self.save();
+
+        self.send_message(
+            chat_id,
+            self.text(
+                t,
+                "callat-done",
+                Args::new().user(from_user).schedule(&description),
+            ),
+        )
+        .remove_later_30s(self, msg.id)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_schedules(&self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let schedules = self.schedules.for_chat(msg.chat.id);
+        let text = if schedules.is_empty() {
+            self.text(t, "schedules-empty", Args::new().user(from_user))
+        } else {
+            let list = schedules
+                .iter()
+                .map(|schedule| schedule.describe(t.locale))
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.text(
+                t,
+                "schedules-list",
+                Args::new().user(from_user).schedule(&list),
+            )
+        };
+
+        self.send_message(msg.chat.id, text)
+            .remove_later_30s(self, msg.id)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unschedule_call(&mut self, msg: Message, id: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let chat_id = msg.chat.id;
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let Ok(id) = id.trim().trim_start_matches('#').parse::<u64>() else {
+            self.send_message(
+                chat_id,
+                self.text(t, "unschedule-usage", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        };
+
+        let Some(schedule) = self.schedules.get(chat_id, id) else {
+            self.send_message(
+                chat_id,
+                self.text(t, "unschedule-not-found", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        };
+
+        let description = schedule.describe(t.locale);
+        if schedule.creator.id != from_user.id && !self.is_admin(&msg).await? {
+            self.send_message(
+                chat_id,
+                self.text(
+                    t,
+                    "unschedule-forbidden",
+                    Args::new().user(from_user).schedule(&description),
+                ),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        self.schedules.remove(chat_id, id);
+        self.save();
+
+        self.send_message(
+            chat_id,
+            self.text(
+                t,
+                "unschedule-done",
+                Args::new().user(from_user).schedule(&description),
+            ),
+        )
+        .remove_later_30s(self, msg.id)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn fire_due_schedules(&mut self) {
+        let due = self.schedules.take_due(self.clock.now());
+        if due.is_empty() {
+            return;
+        }
+
+        for schedule in &due {
+            let span = tracing::info_span!(
+                "schedule",
+                chat_id = schedule.chat_id.0,
+                schedule_id = schedule.id
+            );
+
+            self.current_thread = schedule.thread;
+            if let Err(e) = self.scheduled_call(schedule).instrument(span).await {
+                tracing::warn!("scheduled call #{} failed: {:#}", schedule.id, e);
+                if let Some(reporter) = &self.reporter {
+                    reporter.report(Some("callat"), schedule.chat_id, &e).await;
+                }
+            }
+        }
+        self.current_thread = None;
+
+        self.save();
+    }
+
+    /// Calls on behalf of the schedule's creator, whether or not they are still on the list.
+    async fn scheduled_call(&mut self, schedule: &Schedule) -> anyhow::Result<()> {
+        let mention_list = self
+            .callmap
+            .get_call_list(schedule.chat_id, self.roster_topic())
+            .into_iter()
+            .filter(|user| user.id != schedule.creator.id)
+            .collect::<Vec<_>>();
+
+        if mention_list.is_empty() {
+            tracing::info!("nobody to call for schedule #{}", schedule.id);
+            return Ok(());
+        }
+
+        let t = self.chat_text_ctx(schedule.chat_id);
+        self.send_call(
+            t,
+            schedule.chat_id,
+            &schedule.creator.to_user(),
+            &mention_list,
+            schedule.reason.as_deref(),
+            None,
+        )
+        .await
+    }
+
+    async fn open_lobby(&mut self, msg: Message, args: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let chat_id = msg.chat.id;
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let Some((capacity, game)) = lobby::parse_args(args) else {
+            self.send_message(
+                chat_id,
+                self.text(t, "lobby-usage", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        };
+
+        let now = self.clock.now();
+        let lobby = self
+            .lobbies
+            .create(chat_id, self.current_thread, from_user, game, capacity, now)
+            .clone();
+
+        let ct = self.chat_text_ctx(chat_id);
+        let sent = self
+            .send_message(chat_id, self.lobby_text(ct, &lobby, false))
+            .reply_markup(self.lobby_keyboard(ct, &lobby))
+            .send_timed()
+            .await;
+        let sent = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                self.lobbies.remove(lobby.id);
+                return Err(e.into());
+            }
+        };
+        if let Some(lobby) = self.lobbies.get_mut(lobby.id) {
+            lobby.message_id = Some(sent.id);
+        }
+        self.save();
+
+        let roster = self
+            .callmap
+            .get_call_list(chat_id, self.roster_topic())
+            .into_iter()
+            .filter(|user| user.id != from_user.id)
             .collect::<Vec<_>>();
+        if !roster.is_empty() {
+            let args = Args::new()
+                .user(from_user)
+                .game(&lobby.game)
+                .remaining(lobby.remaining())
+                .roster(&roster);
+            self.send_message(chat_id, self.text(ct, "lobby-open", args))
+                .send_timed()
+                .await?;
+        }
 
-        if mention_list.is_empty() {
-            self.send_message(msg.chat.id, "没有其他人捏，叫一个吧")
-                .remove_later_30s(self, msg.id)
+        Ok(())
+    }
+
+    async fn handle_callback(&mut self, q: CallbackQuery) -> anyhow::Result<()> {
+        let Some((id, action)) = q.data.as_deref().and_then(LobbyAction::parse) else {
+            return Ok(());
+        };
+        self.record_command(Command::Lobby(String::new()).name(), "button");
+
+        let toast_ctx = match q.message.as_ref().map(|msg| msg.chat().id) {
+            Some(chat_id) => TextCtx {
+                locale: self.locale_for(chat_id, Some(&q.from)),
+                chat_id,
+            },
+            None => TextCtx {
+                locale: self.config.i18n.default,
+                chat_id: self.config.white_group(),
+            },
+        };
+        let now = self.clock.now();
+
+        let Some(lobby) = self.lobbies.get_mut(id) else {
+            self.bot
+                .answer_callback_query(q.id.clone())
+                .text(self.text(toast_ctx, "lobby-gone", Args::new()))
                 .await?;
             return Ok(());
+        };
+
+        let is_host = q.from.id == lobby.host.id;
+        let mut promoted = None;
+        // What to tell the presser, whom to ping, and whether the lobby changed or is over.
+        let (toast, announce, changed, over) = match action {
+            LobbyAction::Join => match lobby.join(&q.from) {
+                JoinResult::Joined { filled } => {
+                    ("lobby-joined", filled.then_some("lobby-full"), true, false)
+                }
+                JoinResult::Waitlisted => ("lobby-waitlisted", None, true, false),
+                JoinResult::AlreadyIn => ("lobby-already-in", None, false, false),
+            },
+            LobbyAction::Leave => match lobby.leave(q.from.id) {
+                LobbyLeaveResult::Left { promoted: user } => {
+                    promoted = user;
+                    ("lobby-left", None, true, false)
+                }
+                LobbyLeaveResult::NotIn => ("lobby-not-in", None, false, false),
+            },
+            LobbyAction::Check if is_host => {
+                let deadline = now + chrono::Duration::from_std(READY_CHECK).unwrap_or_default();
+                lobby.start_ready_check(deadline);
+                (
+                    "lobby-check-started",
+                    Some("lobby-ready-check"),
+                    true,
+                    false,
+                )
+            }
+            LobbyAction::Ready => match lobby.ready(q.from.id) {
+                ReadyResult::Ready { all } => {
+                    ("lobby-ready-done", all.then_some("lobby-start"), true, all)
+                }
+                ReadyResult::AlreadyReady => ("lobby-ready-done", None, false, false),
+                ReadyResult::NoCheck => ("lobby-no-check", None, false, false),
+                ReadyResult::NotMember => ("lobby-not-in", None, false, false),
+            },
+            LobbyAction::Close if is_host => ("lobby-closed-done", None, true, true),
+            LobbyAction::Check | LobbyAction::Close => ("lobby-host-only", None, false, false),
+        };
+        let lobby = lobby.clone();
+        if over {
+            self.lobbies.remove(id);
+        }
+        self.current_thread = lobby.thread;
+
+        self.bot
+            .answer_callback_query(q.id.clone())
+            .text(self.text(toast_ctx, toast, Args::new()))
+            .await?;
+
+        let t = self.chat_text_ctx(lobby.chat_id);
+        if let Some(key) = announce {
+            self.announce_lobby(t, &lobby, key, &lobby.members).await?;
+        }
+        if let Some(user) = promoted {
+            let user = user.to_user();
+            let args = Args::new().user(&user).game(&lobby.game);
+            self.send_message(lobby.chat_id, self.text(t, "lobby-promoted", args))
+                .send_timed()
+                .await?;
+        }
+        if changed {
+            self.refresh_lobby_message(t, &lobby, over).await;
+            self.save();
         }
 
-        let mention_msg = mention_list.join("\n");
+        Ok(())
+    }
+
+    /// Pings `roster` about the lobby.
+    async fn announce_lobby(
+        &self, t: TextCtx, lobby: &Lobby, key: &str, roster: &[UserRecord],
+    ) -> anyhow::Result<()> {
+        let roster = UserRecord::to_users(roster);
+        let args = Args::new()
+            .game(&lobby.game)
+            .remaining(READY_CHECK.as_secs() as usize)
+            .roster(&roster);
+        self.send_message(lobby.chat_id, self.text(t, key, args))
+            .send_timed()
+            .await?;
+        Ok(())
+    }
+
+    fn lobby_text(&self, t: TextCtx, lobby: &Lobby, closed: bool) -> String {
+        let args = Args::new()
+            .user(&lobby.host.to_user())
+            .game(&lobby.game)
+            .count(lobby.members.len())
+            .capacity(lobby.capacity)
+            .remaining(lobby.remaining())
+            .roster(&UserRecord::to_users(&lobby.members));
+        let mut text = self.text(t, "lobby-message", args);
+
+        if !lobby.waitlist.is_empty() {
+            let args = Args::new()
+                .count(lobby.waitlist.len())
+                .roster(&UserRecord::to_users(&lobby.waitlist));
+            text.push_str("\n\n");
+            text.push_str(&self.text(t, "lobby-waitlist", args));
+        }
 
-        self.send_message(msg.chat.id, format!("正在 Call PU：\n{}\n\n温馨提示：\n使用 /whoregisteredme 可以查看是谁把您拉进来的捏", mention_msg))
-            .parse_mode(teloxide::types::ParseMode::Html)
+        if let Some(check) = &lobby.ready_check {
+            let args = Args::new()
+                .count(check.ready.len())
+                .remaining(lobby.unready().len());
+            text.push_str("\n\n");
+            text.push_str(&self.text(t, "lobby-ready-status", args));
+        }
+
+        if closed {
+            text.push_str("\n\n");
+            text.push_str(&self.text(t, "lobby-closed", Args::new()));
+        }
+
+        text
+    }
+
+    fn lobby_keyboard(&self, t: TextCtx, lobby: &Lobby) -> InlineKeyboardMarkup {
+        let button = |label: &'static str, action: LobbyAction| {
+            InlineKeyboardButton::callback(
+                self.text(t, label, Args::new()),
+                action.callback_data(lobby.id),
+            )
+        };
+
+        let check = if lobby.ready_check.is_some() {
+            button("lobby-button-ready", LobbyAction::Ready)
+        } else {
+            button("lobby-button-check", LobbyAction::Check)
+        };
+
+        InlineKeyboardMarkup::new([
+            vec![
+                button("lobby-button-join", LobbyAction::Join),
+                button("lobby-button-leave", LobbyAction::Leave),
+            ],
+            vec![check, button("lobby-button-close", LobbyAction::Close)],
+        ])
+    }
+
+    /// Edits the lobby message to the current state, closed lobbies lose their buttons.
+    async fn refresh_lobby_message(&self, t: TextCtx, lobby: &Lobby, closed: bool) {
+        let Some(message_id) = lobby.message_id else {
+            return;
+        };
+
+        let mut edit = self
+            .bot
+            .edit_message_text(lobby.chat_id, message_id, self.lobby_text(t, lobby, closed))
+            .parse_mode(teloxide::types::ParseMode::Html);
+        if !closed {
+            edit = edit.reply_markup(self.lobby_keyboard(t, lobby));
+        }
+
+        if let Err(e) = edit.await {
+            METRICS.api_error(&e);
+            tracing::warn!("failed to update lobby #{}: {}", lobby.id, e);
+        }
+    }
+
+    /// Ends overdue ready checks and drops lobbies nobody finished.
+    async fn expire_lobbies(&mut self) {
+        let now = self.clock.now();
+        let expired = self.lobbies.expire_ready_checks(now);
+        let stale = self.lobbies.take_stale(now);
+        if expired.is_empty() && stale.is_empty() {
+            return;
+        }
+
+        for (lobby, dropped) in &expired {
+            let t = self.chat_text_ctx(lobby.chat_id);
+            self.current_thread = lobby.thread;
+
+            // Everyone left was ready, e.g. the only laggard left the lobby.
+            let (key, roster, over) = if dropped.is_empty() {
+                ("lobby-start", &lobby.members, true)
+            } else {
+                ("lobby-ready-timeout", dropped, false)
+            };
+            if over {
+                self.lobbies.remove(lobby.id);
+            }
+
+            if let Err(e) = self.announce_lobby(t, lobby, key, roster).await {
+                tracing::warn!("failed to announce lobby #{}: {:#}", lobby.id, e);
+            }
+            self.refresh_lobby_message(t, lobby, over).await;
+        }
+
+        for lobby in &stale {
+            let t = self.chat_text_ctx(lobby.chat_id);
+            self.refresh_lobby_message(t, lobby, true).await;
+        }
+        self.current_thread = None;
+
+        self.save();
+    }
+
+    /// The lobby's members when replying to a lobby message, the chat's roster otherwise. Sorted
+    /// by user id, so a draw only depends on its seed.
+    fn draw_players(&self, msg: &Message) -> Vec<User> {
+        let mut players = msg
+            .reply_to_message()
+            .and_then(|reply_to| self.lobbies.find_by_message(msg.chat.id, reply_to.id))
+            .map(|lobby| UserRecord::to_users(&lobby.members))
+            .unwrap_or_else(|| self.callmap.get_call_list(msg.chat.id, self.roster_topic()));
+        players.sort_by_key(|user| user.id.0);
+        players
+    }
+
+    /// Sends a draw result with the seed and the player ids it shuffled, enough to replay it.
+    async fn send_draw(
+        &self, msg: &Message, players: &[UserId], seed: u64, result: String,
+    ) -> anyhow::Result<()> {
+        let t = self.text_ctx(msg);
+        let ids = players
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let args = Args::new().seed(seed).count(players.len()).players(&ids);
+        let footer = self.text(t, "draw-seed", args);
+        self.send_message(msg.chat.id, format!("{}\n\n{}", result, footer))
+            .send_timed()
+            .await?;
+        Ok(())
+    }
+
+    async fn draw_empty(&self, msg: &Message, from_user: &User) -> anyhow::Result<()> {
+        let t = self.text_ctx(msg);
+        self.send_message(
+            msg.chat.id,
+            self.text(t, "draw-empty", Args::new().user(from_user)),
+        )
+        .remove_later_30s(self, msg.id)
+        .await?;
+        Ok(())
+    }
+
+    async fn split_teams(&self, msg: Message, teams: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let players = self.draw_players(&msg);
+        if players.is_empty() {
+            return self.draw_empty(&msg, from_user).await;
+        }
+
+        let teams = match teams.trim() {
+            "" => Some(2),
+            teams => teams.parse().ok(),
+        };
+        let Some(teams) = teams.filter(|teams| (2..=players.len()).contains(teams)) else {
+            self.send_message(
+                msg.chat.id,
+                self.text(
+                    t,
+                    "teams-usage",
+                    Args::new().user(from_user).count(players.len().max(2)),
+                ),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        };
+
+        let ids = players.iter().map(|user| user.id).collect::<Vec<_>>();
+        let seed = draw::random_seed();
+        let mut text = self.text(
+            t,
+            "teams-header",
+            Args::new().user(from_user).count(ids.len()),
+        );
+        for (i, team) in draw::split_teams(players, teams, seed).iter().enumerate() {
+            text.push_str("\n\n");
+            text.push_str(&self.text(t, "teams-team", Args::new().count(i + 1).roster(team)));
+        }
+
+        self.send_draw(&msg, &ids, seed, text).await
+    }
+
+    async fn pick_player(&self, msg: Message, reason: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let mut players = self.draw_players(&msg);
+        if players.is_empty() {
+            return self.draw_empty(&msg, from_user).await;
+        }
+
+        let ids = players.iter().map(|user| user.id).collect::<Vec<_>>();
+        let seed = draw::random_seed();
+        draw::shuffle(&mut players, seed);
+
+        let args = Args::new().user(from_user).roster(&players[..1]);
+        let text = match reason.trim() {
+            "" => self.text(t, "pick-result", args),
+            reason => self.text(t, "pick-result-reason", args.reason(reason)),
+        };
+
+        self.send_draw(&msg, &ids, seed, text).await
+    }
+
+    async fn shuffle_order(&self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let mut players = self.draw_players(&msg);
+        if players.is_empty() {
+            return self.draw_empty(&msg, from_user).await;
+        }
+
+        let ids = players.iter().map(|user| user.id).collect::<Vec<_>>();
+        let seed = draw::random_seed();
+        draw::shuffle(&mut players, seed);
+
+        let text = self.text(
+            t,
+            "order-result",
+            Args::new().user(from_user).roster(&players),
+        );
+        self.send_draw(&msg, &ids, seed, text).await
+    }
+
+    /// Rankings over the chat's calls within the retention window, names are not mentioned.
+    async fn show_stats(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        let retention = self.config.history.retention();
+        if let Some(retention) = retention {
+            self.history.prune(self.clock.now(), retention);
+        }
+        let stats = self
+            .history
+            .stats(msg.chat.id, self.config.schedule.timezone_for(msg.chat.id));
+        let days = self.config.history.retention_days;
+
+        let text = if stats.calls > 0 {
+            let mut lines = vec![self.text(t, "stats-callers", Args::new())];
+            lines.extend(
+                stats
+                    .callers
+                    .iter()
+                    .map(|(name, n)| format!("  {} — {}", html::escape(name), n)),
+            );
+            lines.push(self.text(t, "stats-called", Args::new()));
+            lines.extend(
+                stats
+                    .called
+                    .iter()
+                    .map(|(name, n)| format!("  {} — {}", html::escape(name), n)),
+            );
+            lines.push(self.text(t, "stats-hours", Args::new()));
+            lines.extend(stats.busiest_hours.iter().map(|((day, hour), n)| {
+                format!(
+                    "  {} {:02}:00 — {}",
+                    html::escape(t.locale.tr(schedule::weekday_key(*day))),
+                    hour,
+                    n
+                )
+            }));
+            if let Some(rates) = &stats.response_rates {
+                lines.push(self.text(t, "stats-responses", Args::new()));
+                lines.extend(rates.iter().map(|(name, responded, pinged)| {
+                    format!(
+                        "  {} {}% ({}/{})",
+                        html::escape(name),
+                        responded * 100 / pinged,
+                        responded,
+                        pinged
+                    )
+                }));
+            }
+
+            let lines = lines.join("\n");
+            let args = Args::new()
+                .user(from_user)
+                .count(stats.calls)
+                .stats(&lines);
+            match retention {
+                Some(_) => self.text(t, "stats-message", args.days(days)),
+                None => self.text(t, "stats-message-all", args),
+            }
+        } else {
+            match retention {
+                Some(_) => self.text(t, "stats-empty", Args::new().user(from_user).days(days)),
+                None => self.text(t, "stats-empty-all", Args::new().user(from_user)),
+            }
+        };
+
+        self.send_message(msg.chat.id, text)
+            .remove_later_30s(self, msg.id)
             .await?;
+
         Ok(())
     }
 
-    async fn register_user(&mut self, msg: Message) -> anyhow::Result<()> {
+    /// Registering someone else by reply hides the registrar with the chat's anonymous chance.
+    async fn register_user(&mut self, msg: Message, via: RegisterVia) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let chat_id = msg.chat.id;
+        let topic = self.roster_topic();
 
         let Some(ref from) = msg.from else {
             return Ok(());
@@ -447,41 +2347,45 @@ impl BotInner {
 
         if let Some(reply_to) = msg.reply_to_message()
             && let Some(user) = &reply_to.from {
-                let anonymous = rand::random::<u32>().is_multiple_of(10);
+                let anonymous = self.config.register.anonymous_for(chat_id).roll();
 
-                let user_register = if anonymous {
-                    UserRegister {
-                        register: None,
-                        user: user.clone(),
-                    }
-                } else {
-                    UserRegister {
-                        register: Some(from.clone()),
-                        user: user.clone(),
-                    }
-                };
+                let user_register = UserRegister::new(user, from, anonymous);
 
-                match self.callmap.register(chat_id, user_register) {
+                let now = self.clock.now();
+                match self
+                    .callmap
+                    .register(chat_id, topic, user_register, RegisterVia::Reply, now)
+                {
                     CallResult::AlreadyRegistered => {
-                        self.send_message(msg.chat.id, "该用户已经注册过了！")
-                            .remove_later_30s(self, msg.id)
-                            .await?
+                        self.send_message(
+                            msg.chat.id,
+                            self.text(
+                                t,
+                                "register-other-already",
+                                Args::new().user(user).registrar(from),
+                            ),
+                        )
+                        .remove_later_30s(self, msg.id)
+                        .await?
                     }
                     CallResult::Registered => {
+                        self.save();
                         self.send_message(
                             msg.chat.id,
-                            "注册成功！#User# 现在会被 Call 了".replace_user(user.clone()),
+                            self.text(t, "register-done", Args::new().user(user).registrar(from)),
                         )
-                        .parse_mode(teloxide::types::ParseMode::Html)
                         .remove_later_30s(self, msg.id)
                         .await?
                     }
                     CallResult::InBlacklist => {
                         self.send_message(
                             msg.chat.id,
-                            "#User# 在黑名单中，无法注册捏".replace_user(user.clone()),
+                            self.text(
+                                t,
+                                "register-other-blacklisted",
+                                Args::new().user(user).registrar(from),
+                            ),
                         )
-                        .parse_mode(teloxide::types::ParseMode::Html)
                         .remove_later_30s(self, msg.id)
                         .await?
                     }
@@ -489,32 +2393,30 @@ impl BotInner {
                 return Ok(());
             }
 
-        let from = UserRegister {
-            register: Some(from.clone()),
-            user: from.clone(),
-        };
-
-        match self.callmap.register(chat_id, from.clone()) {
+        let user_register = UserRegister::new(from, from, false);
+        match self.callmap.register(chat_id, topic, user_register, via, self.clock.now()) {
             CallResult::AlreadyRegistered => {
-                self.send_message(msg.chat.id, "你已经注册过了！")
-                    .remove_later_30s(self, msg.id)
-                    .await?
+                self.send_message(
+                    msg.chat.id,
+                    self.text(t, "register-self-already", Args::new().user(from)),
+                )
+                .remove_later_30s(self, msg.id)
+                .await?
             }
             CallResult::Registered => {
+                self.save();
                 self.send_message(
                     msg.chat.id,
-                    "注册成功！#User# 现在会被 Call 了".replace_user(from.user),
+                    self.text(t, "register-done", Args::new().user(from).registrar(from)),
                 )
-                .parse_mode(teloxide::types::ParseMode::Html)
                 .remove_later_30s(self, msg.id)
                 .await?
             }
             CallResult::InBlacklist => {
                 self.send_message(
                     msg.chat.id,
-                    "#User# 在黑名单中，你要不先使用 /unblacklist 退一下？".replace_user(from.user),
+                    self.text(t, "register-self-blacklisted", Args::new().user(from)),
                 )
-                .parse_mode(teloxide::types::ParseMode::Html)
                 .remove_later_30s(self, msg.id)
                 .await?
             }
@@ -524,20 +2426,25 @@ impl BotInner {
     }
 
     async fn leave_user(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
         let chat_id = msg.chat.id;
         let Some(user) = msg.from else {
             return Ok(());
         };
 
-        match self.callmap.leave(chat_id, user.clone()) {
+        let now = self.clock.now();
+        match self.callmap.leave(chat_id, self.roster_topic(), user.clone(), now) {
             LeaveResult::NotRegistered => {
-                self.send_message(msg.chat.id, "你还没有注册过！")
-                    .remove_later_30s(self, msg.id)
-                    .await?
+                self.send_message(
+                    msg.chat.id,
+                    self.text(t, "leave-not-registered", Args::new().user(&user)),
+                )
+                .remove_later_30s(self, msg.id)
+                .await?
             }
             LeaveResult::Left => {
-                self.send_message(msg.chat.id, "#User# 已离开被 Call 列表".replace_user(user))
-                    .parse_mode(teloxide::types::ParseMode::Html)
+                self.save();
+                self.send_message(msg.chat.id, self.text(t, "leave-done", Args::new().user(&user)))
                     .remove_later_30s(self, msg.id)
                     .await?
             }
@@ -546,12 +2453,19 @@ impl BotInner {
         Ok(())
     }
 
-    async fn handle_help_request(&self, msg: Message) -> anyhow::Result<()> {
-        let cmd_descriptions = Command::descriptions().to_string();
+    async fn handle_help_request(&self, msg: Message, topic: &str) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
 
-        let sys_status = sys_status();
-
-        let help_msg = format!("{}\n\n{}", cmd_descriptions, sys_status);
+        let help_msg = if topic.trim().is_empty() {
+            html::escape(&Command::descriptions_for(t.locale))
+        } else if let Some(info) = Command::info(topic) {
+            html::escape(&info.detailed_help(t.locale))
+        } else {
+            let Some(ref from_user) = msg.from else {
+                return Ok(());
+            };
+            self.text(t, "help-unknown", Args::new().user(from_user))
+        };
 
         self.send_message(msg.chat.id, help_msg)
             .remove_later_30s(self, msg.id)
@@ -560,6 +2474,144 @@ impl BotInner {
         tracing::info!("send help done");
         Ok(())
     }
+
+    /// Publishes the command menu: members of any group see the public commands, chat
+    /// administrators also see the admin-only ones, private chats get the few that work there, in
+    /// every supported language.
+    async fn register_commands(&self) -> anyhow::Result<()> {
+        let default_locale = self.config.i18n.default;
+
+        for scope in [
+            BotCommandScope::AllGroupChats,
+            BotCommandScope::AllChatAdministrators,
+            BotCommandScope::AllPrivateChats,
+        ] {
+            let commands = |locale| match scope {
+                BotCommandScope::AllChatAdministrators => Command::bot_commands_for(locale, true),
+                BotCommandScope::AllPrivateChats => Command::private_commands_for(locale),
+                _ => Command::bot_commands_for(locale, false),
+            };
+
+            self.bot
+                .set_my_commands(commands(default_locale))
+                .scope(scope.clone())
+                .await?;
+
+            for locale in Locale::ALL {
+                self.bot
+                    .set_my_commands(commands(locale))
+                    .scope(scope.clone())
+                    .language_code(locale.language_code())
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Configured admin users, or administrators of the chat the message was sent in.
+    async fn is_admin(&self, msg: &Message) -> anyhow::Result<bool> {
+        let Some(ref user) = msg.from else {
+            return Ok(false);
+        };
+
+        if self.config.admin.user_ids.contains(&user.id.0) {
+            return Ok(true);
+        }
+
+        let member = self.bot.get_chat_member(msg.chat.id, user.id).await?;
+        Ok(member.is_privileged())
+    }
+
+    async fn handle_status_request(&mut self, msg: Message) -> anyhow::Result<()> {
+        let t = self.text_ctx(&msg);
+        let Some(ref from_user) = msg.from else {
+            return Ok(());
+        };
+
+        if !self.is_admin(&msg).await? {
+            self.send_message(
+                msg.chat.id,
+                self.text(t, "status-forbidden", Args::new().user(from_user)),
+            )
+            .remove_later_30s(self, msg.id)
+            .await?;
+            return Ok(());
+        }
+
+        // Host details aren't for every member, only a pointer goes to the group.
+        let status_msg = html::escape(&format!("{}\n{}", self.bot_status(), sys_status()));
+        let key = match self.send_private(from_user.id, status_msg).send_timed().await {
+            Ok(_) => "status-sent",
+            Err(e) if notify::is_unreachable(&e) => "status-start-private",
+            Err(e) => return Err(e.into()),
+        };
+
+        self.send_message(msg.chat.id, self.text(t, key, Args::new().user(from_user)))
+            .remove_later_30s(self, msg.id)
+            .await?;
+
+        Ok(())
+    }
+
+    /// CPU usage is averaged since the previous request, or since startup for the first one.
+    fn bot_status(&mut self) -> String {
+        let process_usage = refresh_process(&mut self.system)
+            .and_then(|pid| self.system.process(pid))
+            .map(|process| {
+                format!(
+                    "{} MB RSS, {:.2} % CPU",
+                    process.memory() / 1024 / 1024,
+                    process.cpu_usage()
+                )
+            })
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        let uptime = self.clock.now() - self.started_at;
+
+        let rosters = self.callmap.roster_sizes();
+        let roster_sizes = rosters
+            .iter()
+            .map(|(chat_id, size)| format!("{}: {}", chat_id, size))
+            .collect::<Vec<_>>()
+            .join("\n  ");
+
+        let storage_size = std::fs::metadata(self.storage.path())
+            .map(|meta| format!("{} KB", meta.len() / 1024))
+            .unwrap_or_else(|_| "not written yet".to_string());
+
+        let last_api_error = METRICS
+            .last_api_error()
+            .map(|(at, error)| format!("{} {}", at.to_rfc3339(), error))
+            .unwrap_or_else(|| "None".to_string());
+
+        format!(
+            "Bot Status:\nVersion: {}\nProcess: {}\nUptime: {} d {} h {} m\nChats: {}\nRosters:\n  {}\nPending Captchas: {}\nPending Deletions: {}\nScheduled Calls: {}\nOpen Lobbies: {}\nRecorded Calls: {}\nActive Calls: {}\nDM Subscribers: {}\nStorage: {}\nLast API Error: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            process_usage,
+            uptime.num_days(),
+            uptime.num_hours() % 24,
+            uptime.num_minutes() % 60,
+            rosters.len(),
+            roster_sizes,
+            self.callmap.pending_captchas(),
+            self.deletions.len(),
+            self.schedules.len(),
+            self.lobbies.len(),
+            self.history.len(),
+            self.active_calls.len(),
+            self.subscribers.len(),
+            storage_size,
+            last_api_error
+        )
+    }
+}
+
+/// Refreshes the bot's own process, returning its pid.
+fn refresh_process(system: &mut System) -> Option<sysinfo::Pid> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+    Some(pid)
 }
 
 fn sys_status() -> String {
@@ -617,6 +2669,224 @@ fn sys_status() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        ApiConfig, EscalationConfig, StorageConfig,
+        test_util::{ManualClock, TempDir, named_user, user},
+    };
+
+    /// Stands in for the Bot API: messages to groups go through, private ones fail like they do
+    /// for users who never started the bot, and nobody is a chat administrator.
+    async fn mock_api() -> String {
+        let app = axum::Router::new().fallback(|uri: axum::http::Uri, body: String| async move {
+            let request: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+            // teloxide names the method after its payload type, e.g. `/GetChatMember`.
+            let method = uri.path().rsplit('/').next().unwrap_or_default();
+            if method.eq_ignore_ascii_case("getChatMember") {
+                let member = serde_json::json!({
+                    "ok": true,
+                    "result": {
+                        "status": "member",
+                        "user": { "id": request["user_id"], "is_bot": false, "first_name": "U" },
+                    },
+                });
+                return (axum::http::StatusCode::OK, member.to_string());
+            }
+
+            let chat_id = request["chat_id"].as_i64().unwrap_or_default();
+            if chat_id > 0 {
+                let error = serde_json::json!({
+                    "ok": false,
+                    "error_code": 403,
+                    "description": "Forbidden: bot can't initiate conversation with a user",
+                });
+                return (axum::http::StatusCode::FORBIDDEN, error.to_string());
+            }
+
+            let sent = serde_json::json!({
+                "ok": true,
+                "result": {
+                    "message_id": 10,
+                    "date": 1760800000,
+                    "chat": { "id": chat_id, "type": "group", "title": "test" },
+                    "text": request["text"],
+                },
+            });
+            (axum::http::StatusCode::OK, sent.to_string())
+        });
+
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", tcp.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(tcp, app).await });
+        url
+    }
+
+    fn test_config(api_url: String, dir: &TempDir) -> Config {
+        Config {
+            api: ApiConfig {
+                url: Some(api_url),
+                ..ApiConfig::default()
+            },
+            storage: StorageConfig {
+                path: Some(dir.path().join("state.json")),
+            },
+            ..Config::default()
+        }
+    }
+
+    fn bot_inner(api_url: String, dir: &TempDir) -> BotInner {
+        BotInner::new(test_config(api_url, dir), Arc::new(SystemClock)).unwrap()
+    }
+
+    /// A command sent to the group by `from`, optionally in reply to `reply_to`.
+    fn command(text: &str, from: u64, reply_to: Option<i32>) -> Message {
+        let chat = serde_json::json!({ "id": -1, "type": "group", "title": "test" });
+        let mut msg = serde_json::json!({
+            "message_id": 20,
+            "date": 1760800000,
+            "chat": chat,
+            "from": { "id": from, "is_bot": false, "first_name": "U" },
+            "text": text,
+        });
+        if let Some(reply_to) = reply_to {
+            msg["reply_to_message"] = serde_json::json!({
+                "message_id": reply_to,
+                "date": 1760800000,
+                "chat": chat,
+                "text": "call",
+            });
+        }
+        serde_json::from_value(msg).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_call_with_unreachable_subscriber() {
+        let dir = TempDir::new("call");
+        let mut inner = bot_inner(mock_api().await, &dir);
+        inner.subscribers.subscribe(UserId(2));
+
+        let chat_id = ChatId(-1);
+        let t = inner.chat_text_ctx(chat_id);
+        inner
+            .send_call(t, chat_id, &user(1), &[user(2), user(3)], None, None)
+            .await
+            .unwrap();
+
+        // The failed direct message didn't stop the call, it only unsubscribed the user.
+        assert_eq!(inner.active_calls.len(), 1);
+        assert_eq!(inner.history.len(), 1);
+        assert!(!inner.subscribers.is_subscribed(UserId(2)));
+
+        let state = inner.storage.load().unwrap();
+        assert_eq!(state.subscribers.len(), 0);
+        assert_eq!(state.active_calls.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rename_reaches_calls() {
+        let dir = TempDir::new("rename");
+        let mut inner = bot_inner(mock_api().await, &dir);
+        let chat_id = ChatId(-1);
+        let t = inner.chat_text_ctx(chat_id);
+        inner
+            .send_call(t, chat_id, &user(1), &[user(2)], None, None)
+            .await
+            .unwrap();
+
+        let now = inner.clock.now();
+        assert!(inner.refresh_user(chat_id, &named_user(1, "Caller"), now));
+        assert!(inner.refresh_user(chat_id, &named_user(2, "Pinged"), now));
+        assert!(!inner.refresh_user(chat_id, &named_user(2, "Pinged"), now));
+
+        let call = inner.active_calls.latest_by(chat_id, UserId(1)).unwrap();
+        assert_eq!(call.caller.first_name, "Caller");
+        assert_eq!(call.pending[0].first_name, "Pinged");
+    }
+
+    #[tokio::test]
+    async fn test_end_call() {
+        let dir = TempDir::new("end-call");
+        let mut inner = bot_inner(mock_api().await, &dir);
+        let chat_id = ChatId(-1);
+        let t = inner.chat_text_ctx(chat_id);
+        inner
+            .send_call(t, chat_id, &user(1), &[user(2)], None, None)
+            .await
+            .unwrap();
+
+        // Someone else's call can't be ended by a regular member.
+        let cancel = command("/cancelcall", 2, Some(10));
+        inner.end_call(cancel, CallEnd::Cancelled).await.unwrap();
+        assert_eq!(inner.active_calls.len(), 1);
+        // Without a reply only the sender's own calls count.
+        let cancel = command("/cancelcall", 2, None);
+        inner.end_call(cancel, CallEnd::Cancelled).await.unwrap();
+        assert_eq!(inner.active_calls.len(), 1);
+
+        let close = command("/closecall", 1, None);
+        inner.end_call(close, CallEnd::Closed).await.unwrap();
+        assert_eq!(inner.active_calls.len(), 0);
+        assert_eq!(inner.storage.load().unwrap().active_calls.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_escalation() {
+        let dir = TempDir::new("escalation");
+        let clock = Arc::new(ManualClock::at("2026-10-16T12:00:00Z"));
+        let config = Config {
+            escalation: EscalationConfig {
+                policy: EscalationPolicy {
+                    after_minutes: 10,
+                    rounds: 2,
+                    dm: false,
+                },
+                ..EscalationConfig::default()
+            },
+            ..test_config(mock_api().await, &dir)
+        };
+        let mut inner = BotInner::new(config, clock.clone()).unwrap();
+        let chat_id = ChatId(-1);
+        let t = inner.chat_text_ctx(chat_id);
+        inner
+            .send_call(t, chat_id, &user(1), &[user(2), user(3)], None, None)
+            .await
+            .unwrap();
+        let round = |inner: &BotInner| {
+            inner
+                .active_calls
+                .get(chat_id, MessageId(10))
+                .unwrap()
+                .round
+        };
+
+        inner.escalate_calls().await;
+        assert_eq!(round(&inner), 0);
+
+        clock.advance(chrono::Duration::minutes(10));
+        inner.escalate_calls().await;
+        assert_eq!(round(&inner), 1);
+        // The next round waits another interval.
+        inner.escalate_calls().await;
+        assert_eq!(round(&inner), 1);
+
+        // Answering takes a user off the follow-ups.
+        inner.active_calls.respond(chat_id, UserId(2));
+        clock.advance(chrono::Duration::minutes(10));
+        inner.escalate_calls().await;
+        let call = inner.active_calls.get(chat_id, MessageId(10)).unwrap();
+        assert_eq!(call.round, 2);
+        assert_eq!(call.pending, [UserRecord::from(&user(3))]);
+        assert!(call.next_ping.is_none());
+    }
+
+    #[test]
+    fn test_short_call_reason() {
+        assert_eq!(short_call_reason("c 来打牌"), Some("来打牌"));
+        assert_eq!(short_call_reason("C  Apex 三排 "), Some("Apex 三排"));
+        assert_eq!(short_call_reason("c"), None);
+        assert_eq!(short_call_reason("c "), None);
+        assert_eq!(short_call_reason("cc 来"), None);
+    }
+
     #[test]
     fn test_sys_status() {
         println!("{}", sys_status());
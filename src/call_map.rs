@@ -1,21 +1,184 @@
-use teloxide::types::{ChatId, User, UserId};
+use std::collections::HashMap;
 
-#[derive(Clone)]
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, ThreadId, User, UserId};
+
+/// What the bot keeps of a user: enough to mention them, refreshed whenever they show up. Reads
+/// the full `User` snapshots older state files hold.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: UserId,
+    pub first_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// When the names were last checked, from a message or `getChatMember`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refreshed: Option<DateTime<Utc>>,
+}
+
+impl From<&User> for UserRecord {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            username: user.username.clone(),
+            refreshed: None,
+        }
+    }
+}
+
+impl UserRecord {
+    pub fn full_name(&self) -> String {
+        match &self.last_name {
+            Some(last_name) => format!("{} {}", self.first_name, last_name),
+            None => self.first_name.clone(),
+        }
+    }
+
+    /// A `User` to mention, with only the fields the record keeps.
+    pub fn to_user(&self) -> User {
+        User {
+            id: self.id,
+            is_bot: false,
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            username: self.username.clone(),
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    /// Users to mention from their records.
+    pub fn to_users(records: &[Self]) -> Vec<User> {
+        records.iter().map(Self::to_user).collect()
+    }
+
+    /// Takes the current names of `user` if the record is theirs, returns whether any changed.
+    pub fn rename(&mut self, user: &User) -> bool {
+        if self.id != user.id || self.same_names(user) {
+            return false;
+        }
+        self.first_name = user.first_name.clone();
+        self.last_name = user.last_name.clone();
+        self.username = user.username.clone();
+        true
+    }
+
+    fn same_names(&self, user: &User) -> bool {
+        self.first_name == user.first_name
+            && self.last_name == user.last_name
+            && self.username == user.username
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct UserRegister {
-    pub register: Option<User>,
-    pub user: User,
+    /// Unset only for anonymous registrations saved before the registrar was always kept.
+    pub register: Option<UserRecord>,
+    pub user: UserRecord,
+    /// The registrar is kept for the record but never shown.
+    #[serde(default)]
+    pub anonymous: bool,
+}
+
+impl UserRegister {
+    pub fn new(user: &User, register: &User, anonymous: bool) -> Self {
+        Self {
+            register: Some(register.into()),
+            user: user.into(),
+            anonymous,
+        }
+    }
 }
 
-pub struct CallMap(std::collections::HashMap<ChatId, CallMapInner>);
+/// How someone got on the list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterVia {
+    /// `/register` for themselves.
+    Command,
+    /// `r` for themselves.
+    Shortcut,
+    /// `/register` or `r` in reply to their message.
+    Reply,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterAction {
+    /// An `anonymous` registrar is kept but not shown, `by` is only unset in older records.
+    Registered {
+        by: Option<UserRecord>,
+        via: RegisterVia,
+        #[serde(default)]
+        anonymous: bool,
+    },
+    Left,
+    /// Taken off the list because they left or were removed from the group.
+    LeftChat,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegisterEvent {
+    pub at: DateTime<Utc>,
+    pub action: RegisterAction,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CallMap(HashMap<ChatId, CallMapInner>);
 
 type CaptchaAnswer = bool;
-type CaptchaTimeout = std::time::Instant;
+type CaptchaTimeout = DateTime<Utc>;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CallMapInner {
     pub user_register_list: Vec<UserRegister>,
     pub blacklist: Vec<UserId>,
     pub waiting_captcha: Vec<(UserId, CaptchaAnswer, CaptchaTimeout)>,
+    /// Every registration and leave, oldest first, kept after the user leaves.
+    pub register_history: HashMap<UserId, Vec<RegisterEvent>>,
+    /// Lists of forum topics with a roster of their own, keyed by the topic's thread id.
+    pub topic_register_lists: HashMap<i32, Vec<UserRegister>>,
+}
+
+impl CallMapInner {
+    /// The topic's list, or the chat's when `topic` is `None`.
+    fn list(&self, topic: Option<ThreadId>) -> Option<&Vec<UserRegister>> {
+        match topic {
+            None => Some(&self.user_register_list),
+            Some(topic) => self.topic_register_lists.get(&topic.0.0),
+        }
+    }
+
+    fn list_mut(&mut self, topic: Option<ThreadId>) -> &mut Vec<UserRegister> {
+        match topic {
+            None => &mut self.user_register_list,
+            Some(topic) => self.topic_register_lists.entry(topic.0.0).or_default(),
+        }
+    }
+
+    fn lists(&self) -> impl Iterator<Item = &Vec<UserRegister>> {
+        std::iter::once(&self.user_register_list).chain(self.topic_register_lists.values())
+    }
+
+    fn lists_mut(&mut self) -> impl Iterator<Item = &mut Vec<UserRegister>> {
+        std::iter::once(&mut self.user_register_list).chain(self.topic_register_lists.values_mut())
+    }
+}
+
+/// Appends the users of `from` not on `into` yet.
+fn merge_users(into: &mut Vec<UserRegister>, from: Vec<UserRegister>) {
+    for user in from {
+        if !into.iter().any(|u| u.user.id == user.user.id) {
+            into.push(user);
+        }
+    }
 }
 
 impl Default for CallMap {
@@ -37,7 +200,10 @@ pub enum LeaveResult {
 
 pub enum BlacklistResult {
     AlreadyBlacklisted,
-    Blacklisted,
+    /// `left` is whether they were taken off any of the chat's lists.
+    Blacklisted {
+        left: bool,
+    },
 }
 
 pub enum UnblacklistResult {
@@ -47,10 +213,13 @@ pub enum UnblacklistResult {
 
 impl CallMap {
     pub fn new() -> Self {
-        Self(std::collections::HashMap::new())
+        Self(HashMap::new())
     }
 
-    pub fn register(&mut self, chat_id: ChatId, user: UserRegister) -> CallResult {
+    pub fn register(
+        &mut self, chat_id: ChatId, topic: Option<ThreadId>, mut user: UserRegister,
+        via: RegisterVia, now: DateTime<Utc>,
+    ) -> CallResult {
         let entry = self.0.entry(chat_id).or_default();
 
         if entry.blacklist.contains(&user.user.id) {
@@ -58,77 +227,251 @@ impl CallMap {
         }
 
         if !entry
-            .user_register_list
+            .list_mut(topic)
             .iter()
             .any(|u| u.user.id == user.user.id)
         {
-            entry.user_register_list.push(user);
+            entry
+                .register_history
+                .entry(user.user.id)
+                .or_default()
+                .push(RegisterEvent {
+                    at: now,
+                    action: RegisterAction::Registered {
+                        by: user.register.clone(),
+                        via,
+                        anonymous: user.anonymous,
+                    },
+                });
+            user.user.refreshed = Some(now);
+            entry.list_mut(topic).push(user);
             CallResult::Registered
         } else {
             CallResult::AlreadyRegistered
         }
     }
 
-    pub fn leave(&mut self, chat_id: ChatId, this_user: User) -> LeaveResult {
+    pub fn leave(
+        &mut self, chat_id: ChatId, topic: Option<ThreadId>, this_user: User, now: DateTime<Utc>,
+    ) -> LeaveResult {
         let Some(entry) = self.0.get_mut(&chat_id) else {
             return LeaveResult::NotRegistered;
         };
+        if entry.list(topic).is_none() {
+            return LeaveResult::NotRegistered;
+        }
 
-        let before = entry.user_register_list.len();
-        entry
-            .user_register_list
-            .retain(|u| u.user.id != this_user.id);
+        let list = entry.list_mut(topic);
+        let before = list.len();
+        list.retain(|u| u.user.id != this_user.id);
 
-        if entry.user_register_list.len() == before {
+        if list.len() == before {
             LeaveResult::NotRegistered
         } else {
+            entry
+                .register_history
+                .entry(this_user.id)
+                .or_default()
+                .push(RegisterEvent {
+                    at: now,
+                    action: RegisterAction::Left,
+                });
             LeaveResult::Left
         }
     }
 
-    pub fn has_user(&self, chat_id: &ChatId, this_user: &User) -> bool {
+    /// Takes someone who left the group off the list and drops their captcha, and their
+    /// blacklist entry unless `keep_blacklist`. Returns whether they were on the list.
+    pub fn remove_member(
+        &mut self, chat_id: ChatId, user_id: UserId, keep_blacklist: bool, now: DateTime<Utc>,
+    ) -> bool {
+        let Some(entry) = self.0.get_mut(&chat_id) else {
+            return false;
+        };
+
+        entry.waiting_captcha.retain(|(uid, _, _)| *uid != user_id);
+        if !keep_blacklist {
+            entry.blacklist.retain(|&u| u != user_id);
+        }
+
+        let mut removed = false;
+        for list in entry.lists_mut() {
+            let before = list.len();
+            list.retain(|u| u.user.id != user_id);
+            removed |= list.len() != before;
+        }
+        if !removed {
+            return false;
+        }
+
+        entry
+            .register_history
+            .entry(user_id)
+            .or_default()
+            .push(RegisterEvent {
+                at: now,
+                action: RegisterAction::LeftChat,
+            });
+        true
+    }
+
+    /// Moves everything about a group that became a supergroup, merging with whatever the bot
+    /// already knows about the supergroup.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) {
+        let Some(old) = self.0.remove(&from) else {
+            return;
+        };
+
+        let entry = self.0.entry(to).or_default();
+        merge_users(&mut entry.user_register_list, old.user_register_list);
+        for (topic, users) in old.topic_register_lists {
+            merge_users(entry.topic_register_lists.entry(topic).or_default(), users);
+        }
+        for user_id in old.blacklist {
+            if !entry.blacklist.contains(&user_id) {
+                entry.blacklist.push(user_id);
+            }
+        }
+        entry.waiting_captcha.extend(old.waiting_captcha);
+        for (user_id, mut events) in old.register_history {
+            let history = entry.register_history.entry(user_id).or_default();
+            events.append(history);
+            *history = events;
+        }
+    }
+
+    /// Forgets everything about a chat, returns whether there was anything.
+    pub fn remove_chat(&mut self, chat_id: ChatId) -> bool {
+        self.0.remove(&chat_id).is_some()
+    }
+
+    /// Takes the current names of a user seen in the chat, for their own entry and wherever they
+    /// registered someone. Returns whether any name changed.
+    pub fn refresh_user(&mut self, chat_id: ChatId, user: &User, now: DateTime<Utc>) -> bool {
+        let Some(entry) = self.0.get_mut(&chat_id) else {
+            return false;
+        };
+
+        let mut changed = false;
+        for u in entry.lists_mut().flatten() {
+            for record in std::iter::once(&mut u.user).chain(u.register.as_mut()) {
+                if record.id != user.id {
+                    continue;
+                }
+                changed |= record.rename(user);
+                record.refreshed = Some(now);
+            }
+        }
+        changed
+    }
+
+    /// The roster entry checked longest ago, if that was more than `max_age` ago.
+    pub fn stale_user(
+        &self, now: DateTime<Utc>, max_age: chrono::Duration,
+    ) -> Option<(ChatId, UserId)> {
         self.0
-            .get(chat_id)
-            .map(|users| {
-                users
-                    .user_register_list
-                    .iter()
-                    .any(|u| u.user.id == this_user.id)
+            .iter()
+            .flat_map(|(chat_id, entry)| {
+                entry
+                    .lists()
+                    .flatten()
+                    .map(move |u| (u.user.refreshed, *chat_id, u.user.id))
             })
+            .filter(|(refreshed, _, _)| refreshed.is_none_or(|at| now - at > max_age))
+            .min_by_key(|(refreshed, _, _)| *refreshed)
+            .map(|(_, chat_id, user_id)| (chat_id, user_id))
+    }
+
+    /// Marks a user's roster entries as checked without new names, e.g. when `getChatMember`
+    /// failed.
+    pub fn touch_user(&mut self, chat_id: ChatId, user_id: UserId, now: DateTime<Utc>) {
+        let Some(entry) = self.0.get_mut(&chat_id) else {
+            return;
+        };
+
+        for u in entry.lists_mut().flatten() {
+            if u.user.id == user_id {
+                u.user.refreshed = Some(now);
+            }
+        }
+    }
+
+    pub fn has_user(&self, chat_id: &ChatId, topic: Option<ThreadId>, this_user: &User) -> bool {
+        self.0
+            .get(chat_id)
+            .and_then(|users| users.list(topic))
+            .map(|list| list.iter().any(|u| u.user.id == this_user.id))
             .unwrap_or(false)
     }
 
-    pub fn get_call_list(&self, chat_id: ChatId) -> Vec<User> {
+    pub fn get_call_list(&self, chat_id: ChatId, topic: Option<ThreadId>) -> Vec<User> {
         self.0
             .get(&chat_id)
-            .map(|users| {
-                users
-                    .user_register_list
-                    .iter()
-                    .map(|u| u.user.clone())
-                    .collect()
-            })
+            .and_then(|users| users.list(topic))
+            .map(|list| list.iter().map(|u| u.user.to_user()).collect())
             .unwrap_or_default()
     }
 
-    pub fn get_register(&self, chat_id: &ChatId, user: User) -> Option<User> {
-        self.0.get(chat_id).and_then(|users| {
-            users
-                .user_register_list
-                .iter()
-                .find(|u| u.user.id == user.id)
-                .and_then(|u| u.register.clone())
-        })
+    /// Roster size of every chat the bot has state for, topic rosters included.
+    pub fn roster_sizes(&self) -> Vec<(ChatId, usize)> {
+        self.0
+            .iter()
+            .map(|(chat_id, entry)| (*chat_id, entry.lists().map(Vec::len).sum()))
+            .collect()
+    }
+
+    pub fn pending_captchas(&self) -> usize {
+        self.0.values().map(|entry| entry.waiting_captcha.len()).sum()
+    }
+
+    /// Who registered the user, `None` when that is hidden.
+    pub fn get_register(
+        &self, chat_id: &ChatId, topic: Option<ThreadId>, user: User,
+    ) -> Option<User> {
+        self.0
+            .get(chat_id)
+            .and_then(|users| users.list(topic))
+            .and_then(|list| list.iter().find(|u| u.user.id == user.id))
+            .filter(|u| !u.anonymous)
+            .and_then(|u| u.register.as_ref())
+            .map(UserRecord::to_user)
     }
 
-    pub fn blacklist(&mut self, chat_id: ChatId, user_id: UserId) -> BlacklistResult {
+    pub fn register_history(&self, chat_id: &ChatId, user_id: &UserId) -> &[RegisterEvent] {
+        self.0
+            .get(chat_id)
+            .and_then(|entry| entry.register_history.get(user_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Blacklists the user and takes them off every list of the chat.
+    pub fn blacklist(
+        &mut self, chat_id: ChatId, user_id: UserId, now: DateTime<Utc>,
+    ) -> BlacklistResult {
         let entry = self.0.entry(chat_id).or_default();
-        if !entry.blacklist.contains(&user_id) {
-            entry.blacklist.push(user_id);
-            BlacklistResult::Blacklisted
-        } else {
-            BlacklistResult::AlreadyBlacklisted
+        if entry.blacklist.contains(&user_id) {
+            return BlacklistResult::AlreadyBlacklisted;
+        }
+        entry.blacklist.push(user_id);
+
+        let mut left = false;
+        for list in entry.lists_mut() {
+            let before = list.len();
+            list.retain(|u| u.user.id != user_id);
+            left |= list.len() != before;
         }
+        if left {
+            entry
+                .register_history
+                .entry(user_id)
+                .or_default()
+                .push(RegisterEvent {
+                    at: now,
+                    action: RegisterAction::Left,
+                });
+        }
+        BlacklistResult::Blacklisted { left }
     }
 
     pub fn unblacklist(&mut self, chat_id: ChatId, user_id: UserId) -> UnblacklistResult {
@@ -165,15 +508,44 @@ impl CallMap {
             .unwrap_or(false)
     }
 
-    pub fn push_captcha(&mut self, chat_id: ChatId, user_id: UserId, answer: CaptchaAnswer) {
+    /// Drops timed out captchas and returns how many there were.
+    pub fn expire_captchas(&mut self, chat_id: ChatId, now: DateTime<Utc>) -> usize {
+        let Some(entry) = self.0.get_mut(&chat_id) else {
+            return 0;
+        };
+
+        let before = entry.waiting_captcha.len();
+        entry.waiting_captcha.retain(|(_, _, timeout)| *timeout > now);
+        before - entry.waiting_captcha.len()
+    }
+
+    /// Drops the user's captcha if it timed out, returns whether it did.
+    pub fn expire_captcha(&mut self, chat_id: ChatId, user_id: UserId, now: DateTime<Utc>) -> bool {
+        let Some(entry) = self.0.get_mut(&chat_id) else {
+            return false;
+        };
+
+        let before = entry.waiting_captcha.len();
+        entry
+            .waiting_captcha
+            .retain(|(uid, _, timeout)| *uid != user_id || *timeout > now);
+        entry.waiting_captcha.len() != before
+    }
+
+    pub fn push_captcha(
+        &mut self, chat_id: ChatId, user_id: UserId, answer: CaptchaAnswer, now: DateTime<Utc>,
+    ) {
         let entry = self.0.entry(chat_id).or_default();
-        entry.waiting_captcha.push((user_id, answer, std::time::Instant::now() + std::time::Duration::from_secs(30)));
+        entry
+            .waiting_captcha
+            .push((user_id, answer, now + chrono::Duration::seconds(30)));
     }
 
-    pub fn pop_captcha(&mut self, chat_id: ChatId, user_id: &UserId) -> Option<CaptchaAnswer> {
+    pub fn pop_captcha(
+        &mut self, chat_id: ChatId, user_id: &UserId, now: DateTime<Utc>,
+    ) -> Option<CaptchaAnswer> {
         let entry = self.0.get_mut(&chat_id)?;
 
-        let now = std::time::Instant::now();
         entry.waiting_captcha.retain(|(_, _, timeout)| *timeout > now);
 
         if let Some(pos) = entry
@@ -188,3 +560,308 @@ impl CallMap {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use super::*;
+    use crate::test_util::user;
+
+    #[test]
+    fn test_register_history() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let (a, b) = (user(1), user(2));
+
+        let by_b = UserRegister::new(&a, &b, false);
+        callmap.register(chat_id, None, by_b.clone(), RegisterVia::Reply, Utc::now());
+        // Already on the list, nothing to record.
+        callmap.register(chat_id, None, by_b, RegisterVia::Reply, Utc::now());
+        callmap.leave(chat_id, None, a.clone(), Utc::now());
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&a, &a, false),
+            RegisterVia::Shortcut,
+            Utc::now(),
+        );
+
+        let history = callmap.register_history(&chat_id, &a.id);
+        assert_eq!(history.len(), 3);
+        assert!(matches!(
+            &history[0].action,
+            RegisterAction::Registered { by: Some(by), via: RegisterVia::Reply, .. }
+                if by.id == b.id
+        ));
+        assert!(matches!(history[1].action, RegisterAction::Left));
+        assert!(matches!(
+            history[2].action,
+            RegisterAction::Registered {
+                via: RegisterVia::Shortcut,
+                ..
+            }
+        ));
+        assert!(callmap.register_history(&chat_id, &b.id).is_empty());
+    }
+
+    #[test]
+    fn test_anonymous_register() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let (a, b) = (user(1), user(2));
+
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&a, &b, true),
+            RegisterVia::Reply,
+            Utc::now(),
+        );
+
+        // The registrar is on record but not given away.
+        let history = callmap.register_history(&chat_id, &a.id);
+        assert!(matches!(
+            &history[0].action,
+            RegisterAction::Registered { by: Some(by), anonymous: true, .. } if by.id == b.id
+        ));
+        assert!(callmap.get_register(&chat_id, None, a.clone()).is_none());
+
+        callmap.leave(chat_id, None, a.clone(), Utc::now());
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&a, &b, false),
+            RegisterVia::Reply,
+            Utc::now(),
+        );
+        assert_eq!(callmap.get_register(&chat_id, None, a).unwrap().id, b.id);
+    }
+
+    #[test]
+    fn test_remove_member() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let (a, b) = (user(1), user(2));
+
+        for user in [&a, &b] {
+            callmap.register(
+                chat_id,
+                None,
+                UserRegister::new(user, user, false),
+                RegisterVia::Command,
+                Utc::now(),
+            );
+        }
+        // Blacklisting takes people off the list, so put them on the blacklist directly.
+        let entry = callmap.0.get_mut(&chat_id).unwrap();
+        entry.blacklist.extend([a.id, b.id]);
+
+        assert!(callmap.remove_member(chat_id, a.id, true, Utc::now()));
+        assert!(!callmap.remove_member(chat_id, a.id, true, Utc::now()));
+        assert!(callmap.remove_member(chat_id, b.id, false, Utc::now()));
+
+        assert!(callmap.get_call_list(chat_id, None).is_empty());
+        assert!(callmap.is_blacklisted(&chat_id, &a.id));
+        assert!(!callmap.is_blacklisted(&chat_id, &b.id));
+        assert!(matches!(
+            callmap.register_history(&chat_id, &a.id).last().unwrap().action,
+            RegisterAction::LeftChat
+        ));
+
+        assert!(callmap.remove_chat(chat_id));
+        assert!(!callmap.remove_chat(chat_id));
+    }
+
+    #[test]
+    fn test_refresh_user() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let (a, b) = (user(1), user(2));
+        let now = Utc::now();
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&a, &b, false),
+            RegisterVia::Reply,
+            now,
+        );
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&b, &b, false),
+            RegisterVia::Command,
+            now,
+        );
+
+        let later = now + chrono::Duration::days(2);
+        assert!(callmap.stale_user(later, chrono::Duration::days(1)).is_some());
+        assert!(!callmap.refresh_user(chat_id, &b, later));
+
+        let mut renamed = b.clone();
+        renamed.username = Some("new_name".to_string());
+        assert!(callmap.refresh_user(chat_id, &renamed, later));
+        assert_eq!(callmap.get_register(&chat_id, None, a.clone()), Some(renamed.clone()));
+        assert_eq!(callmap.get_call_list(chat_id, None)[1].username.as_deref(), Some("new_name"));
+
+        // Only `a` is left unchecked since `now`.
+        assert_eq!(
+            callmap.stale_user(later, chrono::Duration::days(1)),
+            Some((chat_id, a.id))
+        );
+        callmap.touch_user(chat_id, a.id, later);
+        assert!(callmap.stale_user(later, chrono::Duration::days(1)).is_none());
+    }
+
+    #[test]
+    fn test_topic_rosters() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let topic = Some(ThreadId(MessageId(7)));
+        let (a, b) = (user(1), user(2));
+        callmap.register(
+            chat_id,
+            None,
+            UserRegister::new(&a, &a, false),
+            RegisterVia::Command,
+            Utc::now(),
+        );
+        callmap.register(
+            chat_id,
+            topic,
+            UserRegister::new(&b, &b, false),
+            RegisterVia::Command,
+            Utc::now(),
+        );
+
+        assert!(callmap.has_user(&chat_id, None, &a));
+        assert!(!callmap.has_user(&chat_id, topic, &a));
+        assert_eq!(
+            callmap.get_call_list(chat_id, topic),
+            std::slice::from_ref(&b)
+        );
+        let other = Some(ThreadId(MessageId(8)));
+        assert!(matches!(
+            callmap.leave(chat_id, other, b.clone(), Utc::now()),
+            LeaveResult::NotRegistered
+        ));
+        assert_eq!(callmap.roster_sizes(), [(chat_id, 2)]);
+
+        assert!(callmap.remove_member(chat_id, b.id, true, Utc::now()));
+        assert!(callmap.get_call_list(chat_id, topic).is_empty());
+    }
+
+    #[test]
+    fn test_blacklist_topics() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let (first, second) = (Some(ThreadId(MessageId(7))), Some(ThreadId(MessageId(8))));
+        let (a, b) = (user(1), user(2));
+
+        for topic in [None, first, second] {
+            callmap.register(
+                chat_id,
+                topic,
+                UserRegister::new(&a, &a, false),
+                RegisterVia::Command,
+                Utc::now(),
+            );
+        }
+        callmap.register(
+            chat_id,
+            first,
+            UserRegister::new(&b, &b, false),
+            RegisterVia::Command,
+            Utc::now(),
+        );
+
+        // Off every list, not just the topic the command came from.
+        assert!(matches!(
+            callmap.blacklist(chat_id, a.id, Utc::now()),
+            BlacklistResult::Blacklisted { left: true }
+        ));
+        for topic in [None, first, second] {
+            assert!(!callmap.has_user(&chat_id, topic, &a));
+        }
+        assert_eq!(
+            callmap.get_call_list(chat_id, first),
+            std::slice::from_ref(&b)
+        );
+        assert_eq!(callmap.register_history(&chat_id, &a.id).len(), 4);
+        assert!(matches!(
+            callmap.register(
+                chat_id,
+                second,
+                UserRegister::new(&a, &a, false),
+                RegisterVia::Command,
+                Utc::now(),
+            ),
+            CallResult::InBlacklist
+        ));
+
+        assert!(matches!(
+            callmap.blacklist(chat_id, a.id, Utc::now()),
+            BlacklistResult::AlreadyBlacklisted
+        ));
+        assert!(matches!(
+            callmap.blacklist(chat_id, b.id, Utc::now()),
+            BlacklistResult::Blacklisted { left: true }
+        ));
+        assert!(matches!(
+            callmap.blacklist(chat_id, user(3).id, Utc::now()),
+            BlacklistResult::Blacklisted { left: false }
+        ));
+    }
+
+    #[test]
+    fn test_captcha_timeout() {
+        let mut callmap = CallMap::new();
+        let chat_id = ChatId(-1);
+        let now = Utc::now();
+        let later = now + chrono::Duration::seconds(31);
+
+        callmap.push_captcha(chat_id, UserId(1), true, now);
+        callmap.push_captcha(chat_id, UserId(2), false, now);
+        assert_eq!(callmap.pop_captcha(chat_id, &UserId(1), now), Some(true));
+        // Answered in time, nothing left to time out.
+        assert!(!callmap.expire_captcha(chat_id, UserId(1), later));
+
+        assert!(!callmap.expire_captcha(chat_id, UserId(2), now));
+        assert!(callmap.expire_captcha(chat_id, UserId(2), later));
+        assert_eq!(callmap.pop_captcha(chat_id, &UserId(2), later), None);
+
+        callmap.push_captcha(chat_id, UserId(3), true, now);
+        assert_eq!(callmap.expire_captchas(chat_id, now), 0);
+        assert_eq!(callmap.expire_captchas(chat_id, later), 1);
+    }
+
+    #[test]
+    fn test_migrate_chat() {
+        let mut callmap = CallMap::new();
+        let (group, supergroup) = (ChatId(-1), ChatId(-1001));
+        let (a, b) = (user(1), user(2));
+
+        for (chat_id, user) in [(group, &a), (group, &b), (supergroup, &b)] {
+            callmap.register(
+                chat_id,
+                None,
+                UserRegister::new(user, user, false),
+                RegisterVia::Command,
+                Utc::now(),
+            );
+        }
+        callmap.blacklist(group, UserId(3), Utc::now());
+
+        callmap.migrate_chat(group, supergroup);
+
+        let ids = callmap
+            .get_call_list(supergroup, None)
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [b.id, a.id]);
+        assert!(callmap.is_blacklisted(&supergroup, &UserId(3)));
+        assert_eq!(callmap.register_history(&supergroup, &b.id).len(), 2);
+        assert!(callmap.get_call_list(group, None).is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId, ThreadId, User, UserId};
+
+use crate::call_map::UserRecord;
+
+/// Calls are no longer followed up or tallied after this long.
+const CALL_TTL: chrono::Duration = chrono::Duration::hours(12);
+
+/// An answer to a call, given by reacting to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rsvp {
+    Coming,
+    NotComing,
+    Later,
+}
+
+impl Rsvp {
+    pub const ALL: [Rsvp; 3] = [Rsvp::Coming, Rsvp::NotComing, Rsvp::Later];
+}
+
+/// How a call was ended before it expired.
+#[derive(Clone, Copy)]
+pub enum CallEnd {
+    /// Called off, nobody needs to come anymore.
+    Cancelled,
+    /// Enough people answered.
+    Closed,
+}
+
+/// A sent call, tracked for its answers and follow-ups.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ActiveCall {
+    pub chat_id: ChatId,
+    pub thread: Option<ThreadId>,
+    /// The call message, which identifies the call within its chat.
+    pub message_id: MessageId,
+    /// The call message as sent, the tally of answers is appended to it.
+    #[serde(default)]
+    pub text: String,
+    /// Link to the call message for private reminders, unset for basic groups.
+    pub link: Option<String>,
+    pub caller: UserRecord,
+    /// Pinged users who haven't answered yet.
+    pub pending: Vec<UserRecord>,
+    /// Everyone who reacted to the call, pinged or not.
+    #[serde(default)]
+    pub answers: Vec<(UserRecord, Rsvp)>,
+    #[serde(default)]
+    pub sent_at: DateTime<Utc>,
+    /// Follow-up pings sent so far.
+    pub round: u32,
+    /// When the non-responders are pinged again, unset once follow-ups are over.
+    pub next_ping: Option<DateTime<Utc>>,
+}
+
+impl ActiveCall {
+    /// The users who answered `rsvp`, in the order they did.
+    pub fn answered(&self, rsvp: Rsvp) -> Vec<&UserRecord> {
+        self.answers
+            .iter()
+            .filter(|(_, answer)| *answer == rsvp)
+            .map(|(user, _)| user)
+            .collect()
+    }
+
+    fn remove_pending(&mut self, user_id: UserId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|user| user.id != user_id);
+        if self.pending.is_empty() {
+            self.next_ping = None;
+        }
+        self.pending.len() != before
+    }
+}
+
+/// Recent calls of every chat.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActiveCalls {
+    calls: Vec<ActiveCall>,
+}
+
+impl ActiveCalls {
+    pub fn add(&mut self, call: ActiveCall) {
+        self.calls.push(call);
+    }
+
+    pub fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<&ActiveCall> {
+        self.calls
+            .iter()
+            .find(|call| call.chat_id == chat_id && call.message_id == message_id)
+    }
+
+    /// The user's most recent call in the chat.
+    pub fn latest_by(&self, chat_id: ChatId, user_id: UserId) -> Option<&ActiveCall> {
+        self.calls
+            .iter()
+            .filter(|call| call.chat_id == chat_id && call.caller.id == user_id)
+            .max_by_key(|call| call.message_id.0)
+    }
+
+    pub fn remove(&mut self, chat_id: ChatId, message_id: MessageId) -> Option<ActiveCall> {
+        let index = self
+            .calls
+            .iter()
+            .position(|call| call.chat_id == chat_id && call.message_id == message_id)?;
+        Some(self.calls.remove(index))
+    }
+
+    /// Marks the user as having answered every call of the chat. Returns whether anything
+    /// changed.
+    pub fn respond(&mut self, chat_id: ChatId, user_id: UserId) -> bool {
+        let mut changed = false;
+        for call in self.calls.iter_mut().filter(|call| call.chat_id == chat_id) {
+            changed |= call.remove_pending(user_id);
+        }
+        changed
+    }
+
+    /// Sets the user's answer to a call, `None` withdraws it. Returns the call when the answer
+    /// changed.
+    pub fn rsvp(
+        &mut self, chat_id: ChatId, message_id: MessageId, user: &User, rsvp: Option<Rsvp>,
+    ) -> Option<&ActiveCall> {
+        let call = self
+            .calls
+            .iter_mut()
+            .find(|call| call.chat_id == chat_id && call.message_id == message_id)?;
+
+        let previous = call
+            .answers
+            .iter()
+            .find(|(answered, _)| answered.id == user.id)
+            .map(|(_, answer)| *answer);
+        if previous == rsvp {
+            return None;
+        }
+
+        call.answers.retain(|(answered, _)| answered.id != user.id);
+        if let Some(rsvp) = rsvp {
+            call.answers.push((user.into(), rsvp));
+            call.remove_pending(user.id);
+        }
+        Some(call)
+    }
+
+    /// Takes the calls whose next follow-up is due, the caller puts them back.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<ActiveCall> {
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.calls)
+            .into_iter()
+            .partition(|call| call.next_ping.is_some_and(|at| at <= now));
+        self.calls = waiting;
+        due
+    }
+
+    /// Stops tracking calls older than [`CALL_TTL`], returning how many there were.
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.calls.len();
+        self.calls.retain(|call| now - call.sent_at < CALL_TTL);
+        before - self.calls.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Takes the current names of a user wherever they appear in a call. Returns whether any
+    /// name changed.
+    pub fn refresh_user(&mut self, user: &User) -> bool {
+        let mut changed = false;
+        for call in &mut self.calls {
+            let answered = call.answers.iter_mut().map(|(record, _)| record);
+            for record in std::iter::once(&mut call.caller)
+                .chain(&mut call.pending)
+                .chain(answered)
+            {
+                changed |= record.rename(user);
+            }
+        }
+        changed
+    }
+
+    /// Moves the calls of a group that became a supergroup.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) {
+        for call in self.calls.iter_mut().filter(|call| call.chat_id == from) {
+            call.chat_id = to;
+        }
+    }
+
+    /// Drops every call of the chat, returning how many there were.
+    pub fn remove_chat(&mut self, chat_id: ChatId) -> usize {
+        let before = self.calls.len();
+        self.calls.retain(|call| call.chat_id != chat_id);
+        before - self.calls.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{named_user, user};
+
+    fn call(message_id: i32, pending: &[u64], next_ping: DateTime<Utc>) -> ActiveCall {
+        ActiveCall {
+            chat_id: ChatId(-1),
+            thread: None,
+            message_id: MessageId(message_id),
+            text: String::new(),
+            link: None,
+            caller: (&user(1)).into(),
+            pending: pending.iter().map(|&id| (&user(id)).into()).collect(),
+            answers: Vec::new(),
+            sent_at: next_ping,
+            round: 0,
+            next_ping: Some(next_ping),
+        }
+    }
+
+    #[test]
+    fn test_respond() {
+        let now = Utc::now();
+        let mut calls = ActiveCalls::default();
+        calls.add(call(10, &[2, 3], now));
+        calls.add(call(11, &[2], now));
+
+        assert!(calls.respond(ChatId(-1), UserId(2)));
+        assert!(!calls.respond(ChatId(-1), UserId(2)));
+        assert!(!calls.respond(ChatId(-2), UserId(3)));
+
+        assert_eq!(
+            calls.get(ChatId(-1), MessageId(10)).unwrap().pending.len(),
+            1
+        );
+        // Everyone answered, no more follow-ups.
+        assert!(
+            calls
+                .get(ChatId(-1), MessageId(11))
+                .unwrap()
+                .next_ping
+                .is_none()
+        );
+        assert_eq!(calls.take_due(now).len(), 1);
+    }
+
+    #[test]
+    fn test_take_due() {
+        let now = Utc::now();
+        let mut calls = ActiveCalls::default();
+        calls.add(call(10, &[2], now));
+        calls.add(call(11, &[2], now + chrono::Duration::minutes(5)));
+
+        let due = calls.take_due(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message_id, MessageId(10));
+        assert_eq!(
+            calls.latest_by(ChatId(-1), UserId(1)).unwrap().message_id,
+            MessageId(11)
+        );
+        assert!(calls.take_due(now).is_empty());
+    }
+
+    #[test]
+    fn test_end() {
+        let now = Utc::now();
+        let mut calls = ActiveCalls::default();
+        calls.add(call(10, &[2], now));
+        calls.add(call(12, &[2], now));
+        calls.add(call(11, &[2], now));
+        let mut other = call(13, &[1], now);
+        other.caller = (&user(2)).into();
+        calls.add(other);
+
+        // The caller's newest call, not the last one added nor someone else's.
+        let latest = calls.latest_by(ChatId(-1), UserId(1)).unwrap().message_id;
+        assert_eq!(latest, MessageId(12));
+        assert!(calls.latest_by(ChatId(-1), UserId(3)).is_none());
+        assert!(calls.latest_by(ChatId(-2), UserId(1)).is_none());
+
+        // An ended call gets no more follow-ups or answers.
+        assert!(calls.remove(ChatId(-1), latest).is_some());
+        assert!(calls.remove(ChatId(-1), latest).is_none());
+        assert!(
+            calls
+                .rsvp(ChatId(-1), latest, &user(2), Some(Rsvp::Coming))
+                .is_none()
+        );
+        let due = calls.take_due(now);
+        assert_eq!(due.len(), 3);
+        assert!(due.iter().all(|call| call.message_id != latest));
+    }
+
+    #[test]
+    fn test_rsvp() {
+        let now = Utc::now();
+        let mut calls = ActiveCalls::default();
+        calls.add(call(10, &[2, 3], now));
+        let (chat_id, message_id) = (ChatId(-1), MessageId(10));
+
+        assert!(
+            calls
+                .rsvp(chat_id, message_id, &user(2), Some(Rsvp::Coming))
+                .is_some()
+        );
+        assert!(
+            calls
+                .rsvp(chat_id, message_id, &user(2), Some(Rsvp::Coming))
+                .is_none()
+        );
+        let call = calls
+            .rsvp(chat_id, message_id, &user(4), Some(Rsvp::Later))
+            .unwrap();
+        assert_eq!(call.answered(Rsvp::Coming).len(), 1);
+        assert_eq!(call.answered(Rsvp::Later).len(), 1);
+        assert_eq!(call.pending.len(), 1);
+
+        let call = calls.rsvp(chat_id, message_id, &user(2), None).unwrap();
+        assert!(call.answered(Rsvp::Coming).is_empty());
+        assert!(calls.rsvp(chat_id, MessageId(11), &user(2), None).is_none());
+    }
+
+    #[test]
+    fn test_refresh_user() {
+        let now = Utc::now();
+        let mut calls = ActiveCalls::default();
+        calls.add(call(10, &[2, 3], now));
+        calls.rsvp(ChatId(-1), MessageId(10), &user(4), Some(Rsvp::Later));
+
+        let renamed = |id| named_user(id, "Renamed");
+        assert!(calls.refresh_user(&renamed(1)));
+        assert!(calls.refresh_user(&renamed(3)));
+        assert!(calls.refresh_user(&renamed(4)));
+        assert!(!calls.refresh_user(&renamed(4)));
+        assert!(!calls.refresh_user(&renamed(5)));
+
+        let call = calls.get(ChatId(-1), MessageId(10)).unwrap();
+        assert_eq!(call.caller.first_name, "Renamed");
+        assert_eq!(call.pending[0].first_name, user(2).first_name);
+        assert_eq!(call.pending[1].first_name, "Renamed");
+        assert_eq!(call.answered(Rsvp::Later)[0].first_name, "Renamed");
+    }
+}
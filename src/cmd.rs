@@ -1,25 +1,222 @@
-use teloxide::utils::command::BotCommands;
+use teloxide::{types::BotCommand, utils::command::BotCommands};
 
+use crate::i18n::Locale;
+
+/// Descriptions live in the message catalogs, see [`Command::descriptions_for`].
 #[derive(BotCommands, Clone)]
-#[command(
-    rename_rule = "lowercase",
-    description = "These commands are supported:"
-)]
+#[command(rename_rule = "lowercase")]
 pub enum Command {
-    #[command(description = "查看帮助")]
-    Help,
-    #[command(
-        description = "或 c 一键被打"
-    )]
-    CallPU,
-    #[command(description = "或 r 注册到被 Call 列表")]
+    /// Sent when someone opens the bot's private chat.
+    Start,
+    /// Optionally followed by the command to explain.
+    Help(String),
+    /// Optionally followed by the reason for the call.
+    CallPU(String),
+    CancelCall,
+    CloseCall,
+    /// `[daily|<weekday>] HH:MM [reason]`.
+    CallAt(String),
+    Schedules,
+    /// The schedule number.
+    Unschedule(String),
+    /// `<capacity> <game>`.
+    Lobby(String),
+    /// Optionally followed by the number of teams.
+    Teams(String),
+    /// Optionally followed by what the pick is for.
+    Pick(String),
+    Order,
+    Stats,
     Register,
-    #[command(description = "或 l 离开被 Call 列表")]
     Leave,
-    #[command(description = "查看发送消息者被谁注册")]
     WhoRegisteredMe,
-    #[command(description = "将自己加入 Call 黑名单")]
+    /// `on` or `off`, shows the current setting when empty.
+    Notify(String),
     Blacklist,
-    #[command(description = "将自己从 Call 黑名单移除")]
     Unblacklist,
+    Status,
+}
+
+/// What `/help` and the Telegram command menu know about a command.
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// Catalog key of the one-line description.
+    description: &'static str,
+    /// Catalog key of the detailed usage shown by `/help <command>`.
+    usage: &'static str,
+    /// Plain-text triggers handled like the command.
+    pub aliases: &'static [&'static str],
+    pub admin_only: bool,
+    /// Also offered in the menu of the bot's private chat.
+    pub private: bool,
+}
+
+impl CommandInfo {
+    const fn new(name: &'static str, description: &'static str, usage: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            usage,
+            aliases: &[],
+            admin_only: false,
+            private: false,
+        }
+    }
+
+    const fn aliases(mut self, aliases: &'static [&'static str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    const fn admin_only(mut self) -> Self {
+        self.admin_only = true;
+        self
+    }
+
+    const fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    pub fn description(&self, locale: Locale) -> &'static str {
+        locale.tr(self.description)
+    }
+
+    /// Description, usage examples and aliases.
+    pub fn detailed_help(&self, locale: Locale) -> String {
+        let mut text = format!(
+            "/{} — {}\n\n{}",
+            self.name,
+            locale.tr(self.description),
+            locale.tr(self.usage)
+        );
+        if !self.aliases.is_empty() {
+            text.push_str(&format!("\n\n{} {}", locale.tr("help-aliases"), self.aliases.join(", ")));
+        }
+        text
+    }
+}
+
+/// Every command, in `/help` order.
+pub static COMMANDS: [CommandInfo; 19] = [
+    CommandInfo::new("help", "cmd-help", "usage-help").private(),
+    CommandInfo::new("callpu", "cmd-callpu", "usage-callpu").aliases(&["c", "C"]),
+    CommandInfo::new("cancelcall", "cmd-cancelcall", "usage-cancelcall"),
+    CommandInfo::new("closecall", "cmd-closecall", "usage-closecall"),
+    CommandInfo::new("callat", "cmd-callat", "usage-callat"),
+    CommandInfo::new("schedules", "cmd-schedules", "usage-schedules"),
+    CommandInfo::new("unschedule", "cmd-unschedule", "usage-unschedule"),
+    CommandInfo::new("lobby", "cmd-lobby", "usage-lobby"),
+    CommandInfo::new("teams", "cmd-teams", "usage-teams"),
+    CommandInfo::new("pick", "cmd-pick", "usage-pick"),
+    CommandInfo::new("order", "cmd-order", "usage-order"),
+    CommandInfo::new("stats", "cmd-stats", "usage-stats"),
+    CommandInfo::new("register", "cmd-register", "usage-register").aliases(&["r", "R"]),
+    CommandInfo::new("leave", "cmd-leave", "usage-leave").aliases(&["l", "L", "丨"]),
+    CommandInfo::new("whoregisteredme", "cmd-whoregisteredme", "usage-whoregisteredme"),
+    CommandInfo::new("notify", "cmd-notify", "usage-notify").private(),
+    CommandInfo::new("blacklist", "cmd-blacklist", "usage-blacklist"),
+    CommandInfo::new("unblacklist", "cmd-unblacklist", "usage-unblacklist"),
+    CommandInfo::new("status", "cmd-status", "usage-status").admin_only(),
+];
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Help(_) => "help",
+            Self::CallPU(_) => "callpu",
+            Self::CancelCall => "cancelcall",
+            Self::CloseCall => "closecall",
+            Self::CallAt(_) => "callat",
+            Self::Schedules => "schedules",
+            Self::Unschedule(_) => "unschedule",
+            Self::Lobby(_) => "lobby",
+            Self::Teams(_) => "teams",
+            Self::Pick(_) => "pick",
+            Self::Order => "order",
+            Self::Stats => "stats",
+            Self::Register => "register",
+            Self::Leave => "leave",
+            Self::WhoRegisteredMe => "whoregisteredme",
+            Self::Notify(_) => "notify",
+            Self::Blacklist => "blacklist",
+            Self::Unblacklist => "unblacklist",
+            Self::Status => "status",
+        }
+    }
+
+    /// Finds a command by name or alias, with or without the leading `/`.
+    pub fn info(topic: &str) -> Option<&'static CommandInfo> {
+        let topic = topic.trim();
+        let topic = topic.strip_prefix('/').unwrap_or(topic);
+        let topic = topic.split('@').next().unwrap_or(topic);
+
+        COMMANDS.iter().find(|info| {
+            info.name.eq_ignore_ascii_case(topic) || info.aliases.contains(&topic)
+        })
+    }
+
+    pub fn descriptions_for(locale: Locale) -> String {
+        let mut text = locale.tr("help-header").to_string();
+        for info in &COMMANDS {
+            text.push_str(&format!("\n/{} — {}", info.name, info.description(locale)));
+        }
+        text
+    }
+
+    /// The command menu for `set_my_commands`, admin-only commands only when `admin` is set.
+    pub fn bot_commands_for(locale: Locale, admin: bool) -> Vec<BotCommand> {
+        COMMANDS
+            .iter()
+            .filter(|info| admin || !info.admin_only)
+            .map(|info| BotCommand::new(info.name, info.description(locale)))
+            .collect()
+    }
+
+    /// The command menu of the bot's private chat.
+    pub fn private_commands_for(locale: Locale) -> Vec<BotCommand> {
+        COMMANDS
+            .iter()
+            .filter(|info| info.private)
+            .map(|info| BotCommand::new(info.name, info.description(locale)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_lookup() {
+        assert_eq!(Command::info("register").map(|info| info.name), Some("register"));
+        assert_eq!(Command::info("/leave@callpu_bot").map(|info| info.name), Some("leave"));
+        assert_eq!(Command::info("丨").map(|info| info.name), Some("leave"));
+        assert_eq!(Command::info("C").map(|info| info.name), Some("callpu"));
+        assert!(Command::info("nothing").is_none());
+    }
+
+    #[test]
+    fn test_parse_call_reason() {
+        let Ok(Command::CallPU(reason)) =
+            Command::parse("/callpu 今晚十点 Apex 三排", "callpu_bot")
+        else {
+            panic!("failed to parse /callpu");
+        };
+        assert_eq!(reason, "今晚十点 Apex 三排");
+    }
+
+    #[test]
+    fn test_parse_help_topic() {
+        let Ok(Command::Help(topic)) = Command::parse("/help register", "callpu_bot") else {
+            panic!("failed to parse /help");
+        };
+        assert_eq!(topic, "register");
+
+        let Ok(Command::Help(topic)) = Command::parse("/help", "callpu_bot") else {
+            panic!("failed to parse /help");
+        };
+        assert_eq!(topic, "");
+    }
 }
@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+use crate::{calls::Rsvp, i18n::Locale, logging::LogRotation};
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The group the bot serves, the built-in `WHITE_GROUP` when unset.
+    pub white_group: Option<i64>,
+    /// Receive updates through a local HTTP listener instead of long polling.
+    pub webhook: Option<WebhookConfig>,
+    pub api: ApiConfig,
+    pub storage: StorageConfig,
+    pub shutdown: ShutdownConfig,
+    pub monitoring: MonitoringConfig,
+    pub log: LogConfig,
+    pub admin: AdminConfig,
+    pub i18n: I18nConfig,
+    pub schedule: ScheduleConfig,
+    pub history: HistoryConfig,
+    pub register: RegisterConfig,
+    pub topics: TopicsConfig,
+    pub calls: CallsConfig,
+    pub escalation: EscalationConfig,
+    pub rsvp: RsvpConfig,
+    /// Per-chat message overrides, keyed by chat id and then by message name.
+    pub templates: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MonitoringConfig {
+    /// Local address serving `/metrics`, `/healthz` and `/readyz`, disabled when unset.
+    pub address: Option<SocketAddr>,
+    /// Report unhealthy when no update arrived for this many seconds.
+    pub stale_after_secs: Option<u64>,
+    /// Send `READY`/`WATCHDOG`/`STOPPING` notifications to systemd.
+    pub systemd: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Ops chat receiving error reports and startup/shutdown notices.
+    pub chat_id: Option<i64>,
+    /// Users allowed to run admin commands besides the group's administrators.
+    pub user_ids: Vec<u64>,
+    /// Identical errors within this window are reported once.
+    pub dedup_window_secs: u64,
+    pub max_reports_per_minute: usize,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            chat_id: None,
+            user_ids: Vec::new(),
+            dedup_window_secs: 600,
+            max_reports_per_minute: 5,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// `RUST_LOG`-style directives, overridden by the `RUST_LOG` environment variable.
+    pub filter: String,
+    pub json: bool,
+    /// Also write logs to rotating files in this directory.
+    pub dir: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Number of rotated files to keep, all of them when unset.
+    pub max_files: Option<usize>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+            json: false,
+            dir: None,
+            rotation: LogRotation::default(),
+            max_files: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct I18nConfig {
+    pub default: Locale,
+    /// Reply in the sender's Telegram language when the chat has no locale of its own.
+    pub per_user: bool,
+    /// Per-chat locale, keyed by chat id.
+    pub chats: HashMap<String, Locale>,
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            default: Locale::default(),
+            per_user: true,
+            chats: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Timezone `/callat` times are read in, e.g. `Asia/Shanghai`.
+    pub timezone: Tz,
+    /// Per-chat timezone, keyed by chat id.
+    pub chats: HashMap<String, Tz>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::Asia__Shanghai,
+            chats: HashMap::new(),
+        }
+    }
+}
+
+impl ScheduleConfig {
+    pub fn timezone_for(&self, chat_id: ChatId) -> Tz {
+        self.chats
+            .get(&chat_id.to_string())
+            .copied()
+            .unwrap_or(self.timezone)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Calls older than this are forgotten, `0` keeps them forever.
+    pub retention_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { retention_days: 90 }
+    }
+}
+
+impl HistoryConfig {
+    pub fn retention(&self) -> Option<chrono::Duration> {
+        (self.retention_days > 0).then(|| chrono::Duration::days(self.retention_days as i64))
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CallsConfig {
+    /// Deletes calls this many minutes after `/cancelcall` or `/closecall`, kept when unset.
+    pub delete_ended_after_minutes: Option<u64>,
+}
+
+impl CallsConfig {
+    pub fn delete_ended_after(&self) -> Option<std::time::Duration> {
+        self.delete_ended_after_minutes
+            .map(|minutes| std::time::Duration::from_secs(minutes * 60))
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EscalationConfig {
+    #[serde(flatten)]
+    pub policy: EscalationPolicy,
+    /// Per-chat policy, keyed by chat id.
+    pub chats: HashMap<String, EscalationPolicy>,
+}
+
+impl EscalationConfig {
+    pub fn policy_for(&self, chat_id: ChatId) -> EscalationPolicy {
+        self.chats
+            .get(&chat_id.to_string())
+            .copied()
+            .unwrap_or(self.policy)
+    }
+}
+
+/// How calls follow up on pinged users who don't answer.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EscalationPolicy {
+    /// Minutes to wait for an answer before pinging again, `0` turns escalation off.
+    pub after_minutes: u64,
+    /// Follow-up pings per call.
+    pub rounds: u32,
+    /// From the second round on, also message subscribed non-responders privately.
+    pub dm: bool,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            after_minutes: 0,
+            rounds: 2,
+            dm: false,
+        }
+    }
+}
+
+impl EscalationPolicy {
+    /// The wait between rounds, `None` when escalation is off.
+    pub fn interval(&self) -> Option<chrono::Duration> {
+        (self.after_minutes > 0 && self.rounds > 0)
+            .then(|| chrono::Duration::minutes(self.after_minutes as i64))
+    }
+}
+
+/// Reactions to a call message that count as answers.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RsvpConfig {
+    pub coming: Vec<String>,
+    pub not_coming: Vec<String>,
+    pub later: Vec<String>,
+}
+
+impl Default for RsvpConfig {
+    fn default() -> Self {
+        Self {
+            coming: vec!["👍".to_string(), "🔥".to_string(), "🫡".to_string()],
+            not_coming: vec!["👎".to_string(), "😢".to_string()],
+            later: vec!["🤔".to_string(), "😴".to_string()],
+        }
+    }
+}
+
+impl RsvpConfig {
+    pub fn rsvp_for(&self, emoji: &str) -> Option<Rsvp> {
+        [
+            (&self.coming, Rsvp::Coming),
+            (&self.not_coming, Rsvp::NotComing),
+            (&self.later, Rsvp::Later),
+        ]
+        .into_iter()
+        .find(|(emojis, _)| emojis.iter().any(|e| e == emoji))
+        .map(|(_, rsvp)| rsvp)
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TopicsConfig {
+    /// Give every forum topic its own call list instead of sharing the group's.
+    pub separate_rosters: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RegisterConfig {
+    /// Chance that registering someone else hides the registrar from `/whoregisteredme`.
+    pub anonymous: AnonymousChance,
+    /// Per-chat chance, keyed by chat id.
+    pub chats: HashMap<String, AnonymousChance>,
+    /// Keep the blacklist entry of users who leave or are removed from the group.
+    pub keep_blacklist: bool,
+}
+
+impl Default for RegisterConfig {
+    fn default() -> Self {
+        Self {
+            anonymous: AnonymousChance(0.1),
+            chats: HashMap::new(),
+            keep_blacklist: true,
+        }
+    }
+}
+
+impl RegisterConfig {
+    pub fn anonymous_for(&self, chat_id: ChatId) -> AnonymousChance {
+        self.chats
+            .get(&chat_id.to_string())
+            .copied()
+            .unwrap_or(self.anonymous)
+    }
+}
+
+/// A probability between 0 and 1, or `"always"` / `"never"`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "AnonymousChanceRepr")]
+pub struct AnonymousChance(f64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnonymousChanceRepr {
+    Chance(f64),
+    Word(String),
+}
+
+impl TryFrom<AnonymousChanceRepr> for AnonymousChance {
+    type Error = String;
+
+    fn try_from(repr: AnonymousChanceRepr) -> Result<Self, Self::Error> {
+        match repr {
+            AnonymousChanceRepr::Chance(chance) if (0.0..=1.0).contains(&chance) => {
+                Ok(Self(chance))
+            }
+            AnonymousChanceRepr::Chance(chance) => {
+                Err(format!("anonymous chance {} is not between 0 and 1", chance))
+            }
+            AnonymousChanceRepr::Word(word) => match word.as_str() {
+                "always" => Ok(Self(1.0)),
+                "never" => Ok(Self(0.0)),
+                _ => Err(format!("expected \"always\", \"never\" or a number, got {:?}", word)),
+            },
+        }
+    }
+}
+
+impl AnonymousChance {
+    pub fn roll(self) -> bool {
+        rand::random::<f64>() < self.0
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// State file, defaults to `<data dir>/callpu/state.json`.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Posted to the group before the bot goes down.
+    pub notice: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Bot API server, e.g. a self-hosted `telegram-bot-api` at `http://127.0.0.1:8081`.
+    pub url: Option<String>,
+    /// Proxy for all outbound requests, `http://`, `https://` or `socks5://`.
+    pub proxy: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// Local address the listener binds to, e.g. `127.0.0.1:8443`.
+    pub address: SocketAddr,
+    /// Public URL Telegram posts updates to, usually served by the reverse proxy.
+    pub url: String,
+    #[serde(default = "default_webhook_path")]
+    pub path: String,
+    /// Checked against the `X-Telegram-Bot-Api-Secret-Token` header of every request.
+    pub secret_token: Option<String>,
+    /// Call `setWebhook` on startup. Turn it off to post recorded updates to the listener locally.
+    #[serde(default = "default_true")]
+    pub set_webhook: bool,
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Upload `cert` to Telegram when it is not signed by a public CA.
+    #[serde(default)]
+    pub self_signed: bool,
+}
+
+fn default_webhook_path() -> String {
+    "/webhook".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Config {
+    pub fn white_group(&self) -> ChatId {
+        ChatId(self.white_group.unwrap_or(crate::WHITE_GROUP))
+    }
+
+    /// Points the whitelist and the per-chat settings of a group that became a supergroup at
+    /// its new id. Settings already made for the new id win. Only changes the loaded config,
+    /// returns whether it named the old id, in which case the file still does.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) -> bool {
+        let mut named = self.white_group == Some(from.0);
+        if self.white_group() == from {
+            self.white_group = Some(to.0);
+        }
+
+        named |= rekey(&mut self.i18n.chats, from, to);
+        named |= rekey(&mut self.schedule.chats, from, to);
+        named |= rekey(&mut self.register.chats, from, to);
+        named |= rekey(&mut self.escalation.chats, from, to);
+        named |= rekey(&mut self.templates, from, to);
+        named
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("callpu").join("config.toml"))
+    }
+
+    /// Loads the config from `path`, or from the default location when none is given.
+    ///
+    /// A missing file at the default location is not an error, the bot just runs with defaults.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", path.display()));
+            }
+        };
+
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+}
+
+/// Returns whether `map` had an entry for `from`.
+fn rekey<V>(map: &mut HashMap<String, V>, from: ChatId, to: ChatId) -> bool {
+    let Some(value) = map.remove(&from.to_string()) else {
+        return false;
+    };
+    map.entry(to.to_string()).or_insert(value);
+    true
+}
+
+impl ApiConfig {
+    pub fn parsed_url(&self) -> anyhow::Result<Option<reqwest::Url>> {
+        self.url
+            .as_deref()
+            .map(|url| {
+                reqwest::Url::parse(url).with_context(|| format!("invalid api url: {}", url))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_webhook() {
+        let config: Config = toml::from_str(
+            r#"
+            [webhook]
+            address = "127.0.0.1:8443"
+            url = "https://example.com/callpu"
+            secret_token = "secret"
+            set_webhook = false
+            "#,
+        )
+        .unwrap();
+
+        let webhook = config.webhook.unwrap();
+        assert_eq!(webhook.path, "/webhook");
+        assert_eq!(webhook.secret_token.as_deref(), Some("secret"));
+        assert!(!webhook.set_webhook);
+        assert!(webhook.tls.is_none());
+    }
+
+    #[test]
+    fn test_parse_api() {
+        let config: Config = toml::from_str(
+            r#"
+            [api]
+            url = "http://127.0.0.1:8081"
+            proxy = "socks5://127.0.0.1:1080"
+            "#,
+        )
+        .unwrap();
+
+        let url = config.api.parsed_url().unwrap().unwrap();
+        assert_eq!(url.scheme(), "http");
+        assert_eq!(config.api.proxy.as_deref(), Some("socks5://127.0.0.1:1080"));
+        assert!(Config::default().api.parsed_url().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_schedule_timezones() {
+        let config: Config = toml::from_str(
+            r#"
+            [schedule]
+            timezone = "Europe/Berlin"
+            chats = { "-1001234567890" = "America/New_York" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.schedule.timezone_for(ChatId(-1001234567890)),
+            Tz::America__New_York
+        );
+        assert_eq!(config.schedule.timezone_for(ChatId(-1)), Tz::Europe__Berlin);
+        assert!(toml::from_str::<Config>("schedule.timezone = \"Mars/Olympus\"").is_err());
+    }
+
+    #[test]
+    fn test_migrate_chat() {
+        let mut config: Config = toml::from_str(
+            r#"
+            white_group = -1
+            i18n.chats = { "-1" = "en" }
+            templates."-1" = { call-empty = "nobody" }
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.migrate_chat(ChatId(-1), ChatId(-1001)));
+        assert_eq!(config.white_group(), ChatId(-1001));
+        assert_eq!(config.i18n.chats.get("-1001"), Some(&Locale::En));
+        assert!(config.templates.contains_key("-1001"));
+        assert!(!config.templates.contains_key("-1"));
+        // Nothing left naming the old id.
+        assert!(!config.migrate_chat(ChatId(-1), ChatId(-1001)));
+    }
+
+    #[test]
+    fn test_parse_anonymous_chance() {
+        let config: Config = toml::from_str(
+            r#"
+            [register]
+            anonymous = 0.25
+
+            [register.chats]
+            "-1" = "always"
+            "-2" = "never"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.register.anonymous_for(ChatId(-3)), AnonymousChance(0.25));
+        assert!(config.register.anonymous_for(ChatId(-1)).roll());
+        assert!(!config.register.anonymous_for(ChatId(-2)).roll());
+        assert!(toml::from_str::<Config>("register.anonymous = 2.0").is_err());
+        assert!(toml::from_str::<Config>("register.anonymous = \"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_escalation() {
+        let config: Config = toml::from_str(
+            r#"
+            [escalation]
+            after_minutes = 10
+            dm = true
+
+            [escalation.chats."-1"]
+            after_minutes = 0
+            "#,
+        )
+        .unwrap();
+
+        let policy = config.escalation.policy_for(ChatId(-2));
+        assert_eq!(policy.interval(), Some(chrono::Duration::minutes(10)));
+        assert_eq!(policy.rounds, 2);
+        assert!(policy.dm);
+        assert_eq!(config.escalation.policy_for(ChatId(-1)).interval(), None);
+    }
+}
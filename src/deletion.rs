@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    prelude::*,
+    types::{ChatId, MessageId},
+};
+
+use crate::{metrics::METRICS, schedule::Clock};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingDeletion {
+    pub chat_id: ChatId,
+    pub message_ids: Vec<MessageId>,
+    pub due: DateTime<Utc>,
+}
+
+/// Messages waiting to be cleaned up, kept around so they can be flushed on shutdown and
+/// resumed after a restart.
+#[derive(Clone)]
+pub struct DeletionQueue {
+    clock: Arc<dyn Clock>,
+    inner: Arc<Mutex<DeletionQueueInner>>,
+}
+
+#[derive(Default)]
+struct DeletionQueueInner {
+    next_id: u64,
+    pending: HashMap<u64, PendingDeletion>,
+}
+
+impl DeletionQueue {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            inner: Arc::default(),
+        }
+    }
+
+    pub fn schedule(
+        &self, bot: &teloxide::Bot, chat_id: ChatId, message_ids: Vec<MessageId>, delay: Duration,
+    ) {
+        let due = self.clock.now() + chrono::Duration::from_std(delay).unwrap_or_default();
+        let id = self.insert(PendingDeletion {
+            chat_id,
+            message_ids,
+            due,
+        });
+        self.spawn(bot.clone(), id);
+    }
+
+    /// Re-arms deletions restored from storage, overdue ones fire right away.
+    pub fn resume(&self, bot: &teloxide::Bot, pending: Vec<PendingDeletion>) {
+        for deletion in pending {
+            let id = self.insert(deletion);
+            self.spawn(bot.clone(), id);
+        }
+    }
+
+    pub fn pending(&self) -> Vec<PendingDeletion> {
+        self.inner.lock().unwrap().pending.values().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    fn insert(&self, deletion: PendingDeletion) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.pending.insert(id, deletion);
+        METRICS.pending_deletions.set(inner.pending.len() as i64);
+        id
+    }
+
+    fn spawn(&self, bot: teloxide::Bot, id: u64) {
+        let queue = self.clone();
+
+        tokio::spawn(async move {
+            let Some(deletion) = queue.inner.lock().unwrap().pending.get(&id).cloned() else {
+                return;
+            };
+
+            let delay = (deletion.due - queue.clock.now())
+                .to_std()
+                .unwrap_or_default();
+            tokio::time::sleep(delay).await;
+
+            if let Err(e) = bot
+                .delete_messages(deletion.chat_id, deletion.message_ids)
+                .send()
+                .await
+            {
+                METRICS.api_error(&e);
+            }
+
+            let mut inner = queue.inner.lock().unwrap();
+            inner.pending.remove(&id);
+            METRICS.pending_deletions.set(inner.pending.len() as i64);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_keeps_pending() {
+        let bot = teloxide::Bot::new("0:test");
+        let queue = DeletionQueue::new(Arc::new(crate::schedule::SystemClock));
+        let due = Utc::now() + chrono::Duration::hours(1);
+        queue.resume(
+            &bot,
+            vec![PendingDeletion {
+                chat_id: ChatId(-1),
+                message_ids: vec![MessageId(10), MessageId(11)],
+                due,
+            }],
+        );
+        queue.schedule(
+            &bot,
+            ChatId(-1),
+            vec![MessageId(12)],
+            Duration::from_secs(3600),
+        );
+
+        // Not due yet, so both are still there to be saved on shutdown.
+        assert_eq!(queue.len(), 2);
+        let mut pending = queue.pending();
+        pending.sort_by_key(|deletion| deletion.due);
+        assert_eq!(pending[0].message_ids, [MessageId(10), MessageId(11)]);
+        assert_eq!(pending[0].due, due);
+
+        let restored: Vec<PendingDeletion> =
+            serde_json::from_str(&serde_json::to_string(&pending).unwrap()).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[1].message_ids, [MessageId(12)]);
+    }
+}
@@ -0,0 +1,78 @@
+/// SplitMix64, small and fully specified so anyone can replay a draw from its seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform enough below `n` for a handful of players, the modulo bias is far below 2⁻⁵⁰.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// A fresh seed for every draw, so nobody can time a command to get a favourable one. It is
+/// published with the result, which lets anyone replay the draw.
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+/// Fisher–Yates from the back: for `i` from `len - 1` down to 1, swap `i` with `next % (i + 1)`.
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut rng = SplitMix64(seed);
+    for i in (1..items.len()).rev() {
+        let j = rng.below(i + 1);
+        items.swap(i, j);
+    }
+}
+
+/// Shuffles the players and deals them round-robin, so team sizes differ by at most one.
+pub fn split_teams<T>(mut players: Vec<T>, teams: usize, seed: u64) -> Vec<Vec<T>> {
+    shuffle(&mut players, seed);
+
+    let mut split = (0..teams).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (i, player) in players.into_iter().enumerate() {
+        split[i % teams].push(player);
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64_reference_values() {
+        let mut rng = SplitMix64(1234567);
+        assert_eq!(rng.next_u64(), 6457827717110365317);
+        assert_eq!(rng.next_u64(), 3203168211198807973);
+        assert_eq!(rng.next_u64(), 9817491932198370423);
+    }
+
+    #[test]
+    fn test_shuffle_is_reproducible() {
+        let mut a = (0..10).collect::<Vec<_>>();
+        let mut b = a.clone();
+        shuffle(&mut a, 42);
+        shuffle(&mut b, 42);
+        assert_eq!(a, b);
+
+        let mut sorted = a.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_split_teams_balanced() {
+        let teams = split_teams((0..7).collect(), 3, 7);
+        let mut sizes = teams.iter().map(Vec::len).collect::<Vec<_>>();
+        sizes.sort();
+        assert_eq!(sizes, [2, 2, 3]);
+        assert_eq!(teams, split_teams((0..7).collect(), 3, 7));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{Router, extract::State, http::StatusCode, routing::get};
+use sd_notify::NotifyState;
+
+use crate::{bot::Bot, storage::Storage};
+
+pub static HEALTH: LazyLock<Health> = LazyLock::new(Health::default);
+
+/// How long the liveness probe waits for the handlers to let go of the bot state.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Health {
+    ready: AtomicBool,
+    last_update: Mutex<Option<Instant>>,
+}
+
+impl Health {
+    pub fn set_ready(&self, ready: bool) {
+        if ready {
+            // Count becoming ready as activity, so a fresh start isn't reported stale.
+            self.update_received();
+        }
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn update_received(&self) {
+        *self.last_update.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn since_last_update(&self) -> Option<Duration> {
+        self.last_update.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Fails once no update came in for longer than `stale_after`.
+    fn check_fresh(&self, stale_after: Option<Duration>) -> Result<(), String> {
+        if let Some(stale_after) = stale_after
+            && let Some(silence) = self.since_last_update()
+            && silence > stale_after
+        {
+            return Err(format!("no updates for {}s", silence.as_secs()));
+        }
+
+        Ok(())
+    }
+
+    fn check_running(&self) -> Result<(), String> {
+        if !self.ready.load(Ordering::SeqCst) {
+            return Err("dispatcher not running".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Probe {
+    bot: Bot,
+    storage: Storage,
+    stale_after: Option<Duration>,
+}
+
+impl Probe {
+    pub fn new(bot: Bot, storage: Storage, stale_after: Option<Duration>) -> Self {
+        Self {
+            bot,
+            storage,
+            stale_after,
+        }
+    }
+
+    /// Alive when the handlers aren't stuck holding the bot state and updates keep coming in.
+    pub async fn live(&self) -> Result<(), String> {
+        if !self.bot.is_responsive(LOCK_TIMEOUT).await {
+            return Err(format!("handlers unresponsive for {:?}", LOCK_TIMEOUT));
+        }
+
+        HEALTH.check_fresh(self.stale_after)
+    }
+
+    /// Ready once the dispatcher is running and the state file can be written.
+    pub fn ready(&self) -> Result<(), String> {
+        HEALTH.check_running()?;
+
+        self.storage
+            .check_writable()
+            .map_err(|e| format!("storage not writable: {:#}", e))
+    }
+}
+
+pub fn router(probe: Probe) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(probe)
+}
+
+async fn healthz(State(probe): State<Probe>) -> (StatusCode, String) {
+    match probe.live().await {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e),
+    }
+}
+
+async fn readyz(State(probe): State<Probe>) -> (StatusCode, String) {
+    match probe.ready() {
+        Ok(()) => (StatusCode::OK, "ok".to_string()),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e),
+    }
+}
+
+pub fn notify(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!("sd_notify failed: {}", e);
+    }
+}
+
+/// Pings the systemd watchdog at half its interval, but only while the bot is alive, so a hung
+/// bot gets restarted.
+pub fn spawn_watchdog(probe: Probe) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    let interval = Duration::from_micros(usec) / 2;
+    tracing::info!("systemd watchdog enabled, pinging every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match probe.live().await {
+                Ok(()) => notify(NotifyState::Watchdog),
+                Err(e) => tracing::warn!("skipping watchdog ping: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready() {
+        let health = Health::default();
+        assert!(health.check_running().is_err());
+
+        health.set_ready(true);
+        assert!(health.check_running().is_ok());
+
+        // Shutting down.
+        health.set_ready(false);
+        assert!(health.check_running().is_err());
+    }
+
+    #[test]
+    fn test_stale() {
+        let health = Health::default();
+        let stale_after = Some(Duration::from_secs(60));
+        // Nothing received yet isn't stale, the bot may have just started.
+        assert!(health.check_fresh(stale_after).is_ok());
+
+        health.update_received();
+        assert!(health.check_fresh(stale_after).is_ok());
+
+        *health.last_update.lock().unwrap() = Some(Instant::now() - Duration::from_secs(120));
+        assert_eq!(
+            health.check_fresh(stale_after).unwrap_err(),
+            "no updates for 120s"
+        );
+        assert!(health.check_fresh(None).is_ok());
+    }
+}
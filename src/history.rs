@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId, User, UserId};
+
+/// Entries shown per ranking in `/stats`.
+const TOP: usize = 5;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CallRecord {
+    pub chat_id: ChatId,
+    pub at: DateTime<Utc>,
+    pub caller: UserId,
+    pub pinged: Vec<UserId>,
+    pub reason: Option<String>,
+    /// The call message, so answers to it can be attributed.
+    pub message_id: Option<MessageId>,
+    /// Pinged users that answered the call, filled in from RSVPs.
+    #[serde(default)]
+    pub responded: Vec<UserId>,
+}
+
+/// Past calls of every chat, kept for the configured retention window.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct History {
+    records: Vec<CallRecord>,
+    /// Display names of everyone in `records`, so stats don't need the users to still be around.
+    names: HashMap<UserId, String>,
+}
+
+impl History {
+    pub fn record(
+        &mut self, chat_id: ChatId, caller: &User, pinged: &[User], reason: Option<&str>,
+        message_id: Option<MessageId>, at: DateTime<Utc>,
+    ) {
+        for user in std::iter::once(caller).chain(pinged) {
+            self.names.insert(user.id, user.full_name());
+        }
+
+        self.records.push(CallRecord {
+            chat_id,
+            at,
+            caller: caller.id,
+            pinged: pinged.iter().map(|user| user.id).collect(),
+            reason: reason.map(str::to_string),
+            message_id,
+            responded: Vec::new(),
+        });
+    }
+
+    /// Drops records older than `retention` and the names nobody refers to anymore.
+    pub fn prune(&mut self, now: DateTime<Utc>, retention: chrono::Duration) {
+        self.records.retain(|record| now - record.at <= retention);
+
+        let records = &self.records;
+        self.names.retain(|user_id, _| {
+            records
+                .iter()
+                .any(|record| record.caller == *user_id || record.pinged.contains(user_id))
+        });
+    }
+
+    /// Notes that a pinged user answered the call sent as `message_id`.
+    pub fn respond(&mut self, chat_id: ChatId, message_id: MessageId, user_id: UserId) {
+        let Some(record) = self
+            .records
+            .iter_mut()
+            .find(|record| record.chat_id == chat_id && record.message_id == Some(message_id))
+        else {
+            return;
+        };
+
+        if record.pinged.contains(&user_id) && !record.responded.contains(&user_id) {
+            record.responded.push(user_id);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Takes the current name of a user the records refer to. Returns whether it changed.
+    pub fn refresh_user(&mut self, user: &User) -> bool {
+        let Some(name) = self.names.get_mut(&user.id) else {
+            return false;
+        };
+
+        let full_name = user.full_name();
+        if *name == full_name {
+            return false;
+        }
+        *name = full_name;
+        true
+    }
+
+    /// Moves the calls of a group that became a supergroup.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) {
+        for record in self.records.iter_mut().filter(|record| record.chat_id == from) {
+            record.chat_id = to;
+        }
+    }
+
+    /// Forgets the chat's calls, returning how many there were.
+    pub fn remove_chat(&mut self, chat_id: ChatId) -> usize {
+        let before = self.records.len();
+        self.records.retain(|record| record.chat_id != chat_id);
+        before - self.records.len()
+    }
+
+    fn name(&self, user_id: UserId) -> String {
+        self.names
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
+    /// Aggregates the chat's calls, hours are counted in `tz`.
+    pub fn stats(&self, chat_id: ChatId, tz: Tz) -> Stats {
+        let mut callers = HashMap::<UserId, usize>::new();
+        let mut called = HashMap::<UserId, usize>::new();
+        // Weekdays keyed by their number from Monday, `Weekday` isn't `Ord`.
+        let mut hours = HashMap::<(u8, u32), usize>::new();
+        let mut answers = HashMap::<UserId, (usize, usize)>::new();
+        let mut calls = 0;
+        let mut any_response = false;
+
+        for record in self
+            .records
+            .iter()
+            .filter(|record| record.chat_id == chat_id)
+        {
+            calls += 1;
+            *callers.entry(record.caller).or_default() += 1;
+
+            let local = record.at.with_timezone(&tz);
+            let day = local.weekday().num_days_from_monday() as u8;
+            *hours.entry((day, local.hour())).or_default() += 1;
+
+            for user_id in &record.pinged {
+                *called.entry(*user_id).or_default() += 1;
+
+                let (responded, pinged) = answers.entry(*user_id).or_default();
+                *pinged += 1;
+                if record.responded.contains(user_id) {
+                    *responded += 1;
+                    any_response = true;
+                }
+            }
+        }
+
+        let named = |counts: HashMap<UserId, usize>| {
+            top(counts
+                .into_iter()
+                .map(|(user_id, n)| (self.name(user_id), n)))
+        };
+
+        let response_rates = any_response.then(|| {
+            let mut rates = answers
+                .into_iter()
+                .map(|(user_id, (responded, pinged))| (self.name(user_id), responded, pinged))
+                .collect::<Vec<_>>();
+            rates.sort_by(|a, b| {
+                (b.1 * a.2)
+                    .cmp(&(a.1 * b.2))
+                    .then_with(|| b.2.cmp(&a.2))
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            rates.truncate(TOP);
+            rates
+        });
+
+        Stats {
+            calls,
+            callers: named(callers),
+            called: named(called),
+            busiest_hours: top(hours.into_iter())
+                .into_iter()
+                .filter_map(|((day, hour), n)| Some(((Weekday::try_from(day).ok()?, hour), n)))
+                .collect(),
+            response_rates,
+        }
+    }
+}
+
+/// The `TOP` biggest counts, ties in key order.
+fn top<K: Ord>(counts: impl Iterator<Item = (K, usize)>) -> Vec<(K, usize)> {
+    let mut counts = counts.collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(TOP);
+    counts
+}
+
+pub struct Stats {
+    pub calls: usize,
+    /// Most active callers with their number of calls.
+    pub callers: Vec<(String, usize)>,
+    /// Most pinged users with the number of calls they were pinged in.
+    pub called: Vec<(String, usize)>,
+    /// Busiest weekday and hour slots in the chat's timezone.
+    pub busiest_hours: Vec<((Weekday, u32), usize)>,
+    /// Best responders as (name, responded, pinged), only once any call got an answer.
+    pub response_rates: Option<Vec<(String, usize, usize)>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Asia::Shanghai;
+
+    use super::*;
+    use crate::test_util::named_user;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let (a, b, c) = (named_user(1, "A"), named_user(2, "B"), named_user(3, "C"));
+        let mut history = History::default();
+        // Friday 21:xx in Shanghai.
+        history.record(
+            ChatId(-1),
+            &a,
+            &[b.clone(), c.clone()],
+            None,
+            Some(MessageId(10)),
+            at("2026-10-16T13:00:00Z"),
+        );
+        history.record(
+            ChatId(-1),
+            &a,
+            std::slice::from_ref(&b),
+            Some("Apex"),
+            Some(MessageId(11)),
+            at("2026-10-16T13:30:00Z"),
+        );
+        history.record(
+            ChatId(-1),
+            &c,
+            &[a.clone(), b.clone()],
+            None,
+            Some(MessageId(12)),
+            at("2026-10-17T02:00:00Z"),
+        );
+        history.record(
+            ChatId(-2),
+            &b,
+            std::slice::from_ref(&a),
+            None,
+            None,
+            at("2026-10-17T02:00:00Z"),
+        );
+
+        let stats = history.stats(ChatId(-1), Shanghai);
+        assert_eq!(stats.calls, 3);
+        assert_eq!(stats.callers, [("A".to_string(), 2), ("C".to_string(), 1)]);
+        assert_eq!(stats.called[0], ("B".to_string(), 3));
+        assert_eq!(stats.busiest_hours[0], ((Weekday::Fri, 21), 2));
+        assert!(stats.response_rates.is_none());
+
+        history.respond(ChatId(-1), MessageId(10), UserId(2));
+        history.respond(ChatId(-1), MessageId(10), UserId(2));
+        // The caller isn't among the pinged.
+        history.respond(ChatId(-1), MessageId(10), UserId(1));
+
+        let rates = history.stats(ChatId(-1), Shanghai).response_rates.unwrap();
+        assert_eq!(rates[0], ("B".to_string(), 1, 3));
+        assert_eq!(rates.iter().find(|(name, _, _)| name == "A").unwrap().1, 0);
+    }
+
+    #[test]
+    fn test_prune() {
+        let (a, b) = (named_user(1, "A"), named_user(2, "B"));
+        let mut history = History::default();
+        history.record(
+            ChatId(-1),
+            &a,
+            std::slice::from_ref(&b),
+            None,
+            None,
+            at("2026-01-01T00:00:00Z"),
+        );
+        history.record(ChatId(-1), &b, &[], None, None, at("2026-10-01T00:00:00Z"));
+
+        history.prune(at("2026-10-18T00:00:00Z"), chrono::Duration::days(90));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.name(UserId(1)), "1");
+        assert_eq!(history.name(UserId(2)), "B");
+
+        // Renames reach the names still referred to, and don't bring back pruned ones.
+        assert!(history.refresh_user(&named_user(2, "Bee")));
+        assert!(!history.refresh_user(&named_user(2, "Bee")));
+        assert!(!history.refresh_user(&a));
+        assert_eq!(history.name(UserId(2)), "Bee");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use serde::Deserialize;
+
+type Catalog = HashMap<String, String>;
+
+static ZH_CN: LazyLock<Catalog> = LazyLock::new(|| {
+    toml::from_str(include_str!("../locales/zh-CN.toml")).expect("invalid zh-CN catalog")
+});
+
+static EN: LazyLock<Catalog> = LazyLock::new(|| {
+    toml::from_str(include_str!("../locales/en.toml")).expect("invalid en catalog")
+});
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+    #[serde(rename = "en")]
+    En,
+}
+
+impl Locale {
+    pub const ALL: [Self; 2] = [Self::ZhCn, Self::En];
+
+    /// Maps a Telegram `language_code` (IETF tag such as `en-US` or `zh-hans`) to a locale.
+    pub fn from_language_code(code: &str) -> Option<Self> {
+        let lang = code.split(['-', '_']).next()?.to_ascii_lowercase();
+        match lang.as_str() {
+            "zh" => Some(Self::ZhCn),
+            "en" => Some(Self::En),
+            _ => None,
+        }
+    }
+
+    /// Two-letter code Telegram uses for command menus.
+    pub fn language_code(self) -> &'static str {
+        match self {
+            Self::ZhCn => "zh",
+            Self::En => "en",
+        }
+    }
+
+    fn catalog(self) -> &'static Catalog {
+        match self {
+            Self::ZhCn => &ZH_CN,
+            Self::En => &EN,
+        }
+    }
+
+    /// Looks up `key`, falling back to zh-CN and then to the key itself.
+    pub fn tr(self, key: &'static str) -> &'static str {
+        self.catalog()
+            .get(key)
+            .or_else(|| Locale::ZhCn.catalog().get(key))
+            .map(String::as_str)
+            .unwrap_or(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalogs_have_same_keys() {
+        let mut zh_cn = ZH_CN.keys().collect::<Vec<_>>();
+        let mut en = EN.keys().collect::<Vec<_>>();
+        zh_cn.sort();
+        en.sort();
+        assert_eq!(zh_cn, en);
+    }
+
+    #[test]
+    fn test_from_language_code() {
+        assert_eq!(Locale::from_language_code("en-US"), Some(Locale::En));
+        assert_eq!(Locale::from_language_code("zh-hans"), Some(Locale::ZhCn));
+        assert_eq!(Locale::from_language_code("ja"), None);
+    }
+}
@@ -1,21 +1,56 @@
+use std::path::PathBuf;
+
 use bot::Bot;
-use tracing_subscriber::fmt::time::ChronoLocal;
+use clap::Parser;
 
 mod bot;
 mod call_map;
+mod calls;
 mod cmd;
+mod config;
+mod deletion;
+mod draw;
+mod health;
+mod history;
+mod i18n;
+mod lobby;
+mod logging;
+mod metrics;
+mod migration;
+mod monitoring;
+mod notify;
 mod question;
+mod reporter;
+mod schedule;
 mod msg_prelude;
+mod storage;
+mod template;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod webhook;
 
 pub use call_map::*;
+pub use config::*;
+pub use i18n::Locale;
 pub use msg_prelude::*;
 
 pub const BOT_TOKEN: &str = "";
 pub const WHITE_GROUP: i64 = 0;
 
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Path to the config file, defaults to `<config dir>/callpu/config.toml`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
+
 pub async fn run() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().with_timer(ChronoLocal::rfc_3339()).with_max_level(tracing::Level::INFO).init();
+    let args = Args::parse();
+    let config = Config::load(args.config.as_deref())?;
+
+    let _log_guard = logging::init(&config.log)?;
 
-    let bot: Bot = bot::Bot::new();
+    let bot: Bot = bot::Bot::new(config)?;
     bot.run_active().await
 }
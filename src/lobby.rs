@@ -0,0 +1,473 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId, ThreadId, User, UserId};
+
+use crate::call_map::UserRecord;
+
+/// Lobbies nobody started or closed are dropped after this long.
+const LOBBY_TTL: chrono::Duration = chrono::Duration::hours(12);
+
+pub const MIN_CAPACITY: usize = 2;
+pub const MAX_CAPACITY: usize = 50;
+
+/// Parses `/lobby` arguments, `<capacity> <game>`.
+pub fn parse_args(args: &str) -> Option<(usize, String)> {
+    let (capacity, game) = args.trim().split_once(char::is_whitespace)?;
+    let capacity = capacity.parse().ok()?;
+    let game = game.trim();
+
+    if !(MIN_CAPACITY..=MAX_CAPACITY).contains(&capacity) || game.is_empty() {
+        return None;
+    }
+    Some((capacity, game.to_string()))
+}
+
+/// What a button on the lobby message does, carried in its callback data as `lobby:<id>:<action>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LobbyAction {
+    Join,
+    Leave,
+    /// Host only: start a ready check.
+    Check,
+    Ready,
+    /// Host only.
+    Close,
+}
+
+impl LobbyAction {
+    const ALL: [Self; 5] = [
+        Self::Join,
+        Self::Leave,
+        Self::Check,
+        Self::Ready,
+        Self::Close,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Join => "join",
+            Self::Leave => "leave",
+            Self::Check => "check",
+            Self::Ready => "ready",
+            Self::Close => "close",
+        }
+    }
+
+    pub fn callback_data(self, lobby_id: u64) -> String {
+        format!("lobby:{}:{}", lobby_id, self.name())
+    }
+
+    pub fn parse(data: &str) -> Option<(u64, Self)> {
+        let (id, action) = data.strip_prefix("lobby:")?.split_once(':')?;
+        let action = Self::ALL.into_iter().find(|known| known.name() == action)?;
+        Some((id.parse().ok()?, action))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReadyCheck {
+    pub deadline: DateTime<Utc>,
+    pub ready: Vec<UserId>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Lobby {
+    pub id: u64,
+    pub chat_id: ChatId,
+    /// The forum topic the lobby was opened in.
+    #[serde(default)]
+    pub thread: Option<ThreadId>,
+    /// The lobby message with the buttons, set once it is sent.
+    pub message_id: Option<MessageId>,
+    pub host: UserRecord,
+    pub game: String,
+    /// Number of players including the host.
+    pub capacity: usize,
+    pub members: Vec<UserRecord>,
+    pub waitlist: Vec<UserRecord>,
+    pub ready_check: Option<ReadyCheck>,
+    pub created: DateTime<Utc>,
+}
+
+pub enum JoinResult {
+    Joined { filled: bool },
+    Waitlisted,
+    AlreadyIn,
+}
+
+pub enum LobbyLeaveResult {
+    /// `promoted` moved up from the waitlist into the freed slot.
+    Left {
+        promoted: Option<UserRecord>,
+    },
+    NotIn,
+}
+
+pub enum ReadyResult {
+    NoCheck,
+    NotMember,
+    AlreadyReady,
+    Ready { all: bool },
+}
+
+impl Lobby {
+    pub fn is_full(&self) -> bool {
+        self.members.len() >= self.capacity
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.capacity.saturating_sub(self.members.len())
+    }
+
+    fn is_in(&self, user_id: UserId) -> bool {
+        self.members
+            .iter()
+            .chain(&self.waitlist)
+            .any(|user| user.id == user_id)
+    }
+
+    pub fn join(&mut self, user: &User) -> JoinResult {
+        if self.is_in(user.id) {
+            return JoinResult::AlreadyIn;
+        }
+
+        if self.is_full() {
+            self.waitlist.push(user.into());
+            return JoinResult::Waitlisted;
+        }
+
+        self.members.push(user.into());
+        JoinResult::Joined {
+            filled: self.is_full(),
+        }
+    }
+
+    pub fn leave(&mut self, user_id: UserId) -> LobbyLeaveResult {
+        if let Some(index) = self.waitlist.iter().position(|user| user.id == user_id) {
+            self.waitlist.remove(index);
+            return LobbyLeaveResult::Left { promoted: None };
+        }
+
+        let Some(index) = self.members.iter().position(|user| user.id == user_id) else {
+            return LobbyLeaveResult::NotIn;
+        };
+        self.members.remove(index);
+
+        LobbyLeaveResult::Left {
+            promoted: self.promote(),
+        }
+    }
+
+    /// Moves the first waiting user into a free slot.
+    fn promote(&mut self) -> Option<UserRecord> {
+        if self.is_full() || self.waitlist.is_empty() {
+            return None;
+        }
+
+        let user = self.waitlist.remove(0);
+        self.members.push(user.clone());
+        Some(user)
+    }
+
+    pub fn start_ready_check(&mut self, deadline: DateTime<Utc>) {
+        self.ready_check = Some(ReadyCheck {
+            deadline,
+            ready: Vec::new(),
+        });
+    }
+
+    pub fn ready(&mut self, user_id: UserId) -> ReadyResult {
+        if !self.members.iter().any(|user| user.id == user_id) {
+            return ReadyResult::NotMember;
+        }
+
+        let Some(check) = &mut self.ready_check else {
+            return ReadyResult::NoCheck;
+        };
+
+        if check.ready.contains(&user_id) {
+            return ReadyResult::AlreadyReady;
+        }
+        check.ready.push(user_id);
+
+        ReadyResult::Ready {
+            all: self.unready().is_empty(),
+        }
+    }
+
+    /// Members that haven't confirmed the running ready check.
+    pub fn unready(&self) -> Vec<&UserRecord> {
+        let Some(check) = &self.ready_check else {
+            return Vec::new();
+        };
+
+        self.members
+            .iter()
+            .filter(|user| !check.ready.contains(&user.id))
+            .collect()
+    }
+
+    /// Ends a ready check past its deadline, dropping the members that didn't confirm and
+    /// filling their slots from the waitlist. Returns the dropped members.
+    fn expire_ready_check(&mut self, now: DateTime<Utc>) -> Option<Vec<UserRecord>> {
+        if self.ready_check.as_ref()?.deadline > now {
+            return None;
+        }
+
+        let unready = self
+            .unready()
+            .into_iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>();
+        self.ready_check = None;
+
+        let dropped = self
+            .members
+            .extract_if(.., |user| unready.contains(&user.id))
+            .collect::<Vec<_>>();
+        while self.promote().is_some() {}
+
+        Some(dropped)
+    }
+}
+
+/// Open lobbies of every chat.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lobbies {
+    last_id: u64,
+    lobbies: Vec<Lobby>,
+}
+
+impl Lobbies {
+    /// Opens a lobby with the host as its first member.
+    pub fn create(
+        &mut self, chat_id: ChatId, thread: Option<ThreadId>, host: &User, game: String,
+        capacity: usize, now: DateTime<Utc>,
+    ) -> &mut Lobby {
+        self.last_id += 1;
+        self.lobbies.push(Lobby {
+            id: self.last_id,
+            chat_id,
+            thread,
+            message_id: None,
+            host: host.into(),
+            game,
+            capacity,
+            members: vec![host.into()],
+            waitlist: Vec::new(),
+            ready_check: None,
+            created: now,
+        });
+
+        self.lobbies.last_mut().unwrap()
+    }
+
+    pub fn find_by_message(&self, chat_id: ChatId, message_id: MessageId) -> Option<&Lobby> {
+        self.lobbies
+            .iter()
+            .find(|lobby| lobby.chat_id == chat_id && lobby.message_id == Some(message_id))
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Lobby> {
+        self.lobbies.iter_mut().find(|lobby| lobby.id == id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Lobby> {
+        let index = self.lobbies.iter().position(|lobby| lobby.id == id)?;
+        Some(self.lobbies.remove(index))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lobbies.len()
+    }
+
+    /// Takes the current names of a user wherever they appear in a lobby. Returns whether any
+    /// name changed.
+    pub fn refresh_user(&mut self, user: &User) -> bool {
+        let mut changed = false;
+        for lobby in &mut self.lobbies {
+            for record in std::iter::once(&mut lobby.host)
+                .chain(&mut lobby.members)
+                .chain(&mut lobby.waitlist)
+            {
+                changed |= record.rename(user);
+            }
+        }
+        changed
+    }
+
+    /// Drops every lobby of the chat, returning how many there were.
+    pub fn remove_chat(&mut self, chat_id: ChatId) -> usize {
+        let before = self.lobbies.len();
+        self.lobbies.retain(|lobby| lobby.chat_id != chat_id);
+        before - self.lobbies.len()
+    }
+
+    /// Ends the ready checks past their deadline, see [`Lobby::expire_ready_check`].
+    pub fn expire_ready_checks(&mut self, now: DateTime<Utc>) -> Vec<(Lobby, Vec<UserRecord>)> {
+        self.lobbies
+            .iter_mut()
+            .filter_map(|lobby| {
+                let dropped = lobby.expire_ready_check(now)?;
+                Some((lobby.clone(), dropped))
+            })
+            .collect()
+    }
+
+    /// Takes the lobbies older than [`LOBBY_TTL`].
+    pub fn take_stale(&mut self, now: DateTime<Utc>) -> Vec<Lobby> {
+        self.lobbies
+            .extract_if(.., |lobby| now - lobby.created > LOBBY_TTL)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{named_user, user};
+
+    fn lobby(capacity: usize) -> (Lobbies, u64) {
+        let mut lobbies = Lobbies::default();
+        let id = lobbies
+            .create(
+                ChatId(-1),
+                None,
+                &user(1),
+                "Apex".to_string(),
+                capacity,
+                Utc::now(),
+            )
+            .id;
+        (lobbies, id)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args("5 Apex 三排"),
+            Some((5, "Apex 三排".to_string()))
+        );
+        assert_eq!(parse_args("5"), None);
+        assert_eq!(parse_args("1 Apex"), None);
+        assert_eq!(parse_args("Apex 5"), None);
+    }
+
+    #[test]
+    fn test_callback_data_round_trip() {
+        for action in LobbyAction::ALL {
+            assert_eq!(
+                LobbyAction::parse(&action.callback_data(42)),
+                Some((42, action))
+            );
+        }
+        assert_eq!(LobbyAction::parse("lobby:42:dance"), None);
+        assert_eq!(LobbyAction::parse("other:42:join"), None);
+    }
+
+    #[test]
+    fn test_join_fills_then_waitlists() {
+        let (mut lobbies, id) = lobby(3);
+        let lobby = lobbies.get_mut(id).unwrap();
+
+        assert!(matches!(lobby.join(&user(1)), JoinResult::AlreadyIn));
+        assert!(matches!(
+            lobby.join(&user(2)),
+            JoinResult::Joined { filled: false }
+        ));
+        assert!(matches!(
+            lobby.join(&user(3)),
+            JoinResult::Joined { filled: true }
+        ));
+        assert!(matches!(lobby.join(&user(4)), JoinResult::Waitlisted));
+        assert!(matches!(lobby.join(&user(4)), JoinResult::AlreadyIn));
+        assert_eq!(lobby.remaining(), 0);
+    }
+
+    #[test]
+    fn test_leave_promotes_from_waitlist() {
+        let (mut lobbies, id) = lobby(2);
+        let lobby = lobbies.get_mut(id).unwrap();
+        lobby.join(&user(2));
+        lobby.join(&user(3));
+
+        let LobbyLeaveResult::Left { promoted } = lobby.leave(UserId(2)) else {
+            panic!("user 2 was in the lobby");
+        };
+        assert_eq!(promoted.map(|user| user.id), Some(UserId(3)));
+        assert!(lobby.waitlist.is_empty());
+        assert!(matches!(lobby.leave(UserId(2)), LobbyLeaveResult::NotIn));
+    }
+
+    #[test]
+    fn test_ready_check() {
+        let (mut lobbies, id) = lobby(2);
+        let now = Utc::now();
+        let lobby = lobbies.get_mut(id).unwrap();
+        lobby.join(&user(2));
+
+        assert!(matches!(lobby.ready(UserId(1)), ReadyResult::NoCheck));
+        lobby.start_ready_check(now + chrono::Duration::seconds(60));
+        assert!(matches!(lobby.ready(UserId(3)), ReadyResult::NotMember));
+        assert!(matches!(
+            lobby.ready(UserId(1)),
+            ReadyResult::Ready { all: false }
+        ));
+        assert!(matches!(lobby.ready(UserId(1)), ReadyResult::AlreadyReady));
+        assert!(matches!(
+            lobby.ready(UserId(2)),
+            ReadyResult::Ready { all: true }
+        ));
+    }
+
+    #[test]
+    fn test_ready_check_timeout_drops_unready() {
+        let (mut lobbies, id) = lobby(2);
+        let now = Utc::now();
+        let lobby = lobbies.get_mut(id).unwrap();
+        lobby.join(&user(2));
+        lobby.join(&user(3));
+        lobby.start_ready_check(now + chrono::Duration::seconds(60));
+        lobby.ready(UserId(1));
+
+        assert!(lobbies.expire_ready_checks(now).is_empty());
+
+        let expired = lobbies.expire_ready_checks(now + chrono::Duration::seconds(61));
+        let (lobby, dropped) = &expired[0];
+        assert_eq!(
+            dropped.iter().map(|user| user.id).collect::<Vec<_>>(),
+            [UserId(2)]
+        );
+        assert_eq!(
+            lobby.members.iter().map(|user| user.id).collect::<Vec<_>>(),
+            [UserId(1), UserId(3)]
+        );
+        assert!(lobby.ready_check.is_none());
+    }
+
+    #[test]
+    fn test_refresh_user() {
+        let (mut lobbies, id) = lobby(2);
+        let lobby = lobbies.get_mut(id).unwrap();
+        lobby.join(&user(2));
+        lobby.join(&user(3));
+
+        assert!(lobbies.refresh_user(&named_user(1, "Host")));
+        assert!(lobbies.refresh_user(&named_user(3, "Waiting")));
+        assert!(!lobbies.refresh_user(&named_user(3, "Waiting")));
+
+        let lobby = lobbies.get_mut(id).unwrap();
+        assert_eq!(lobby.host.first_name, "Host");
+        assert_eq!(lobby.members[0].first_name, "Host");
+        assert_eq!(lobby.waitlist[0].first_name, "Waiting");
+    }
+
+    #[test]
+    fn test_stale_lobbies() {
+        let (mut lobbies, _) = lobby(2);
+        assert!(lobbies.take_stale(Utc::now()).is_empty());
+        assert_eq!(lobbies.take_stale(Utc::now() + LOBBY_TTL * 2).len(), 1);
+        assert_eq!(lobbies.len(), 0);
+    }
+}
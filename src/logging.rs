@@ -0,0 +1,153 @@
+use anyhow::Context;
+use serde::Deserialize;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, fmt::time::ChronoLocal, layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+use crate::config::LogConfig;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn fmt_layer<W>(writer: W, json: bool, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_timer(ChronoLocal::rfc_3339())
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    if json { layer.json().boxed() } else { layer.boxed() }
+}
+
+/// A valid `env` filter wins over the configured one.
+fn filter(configured: &str, env: Option<&str>) -> anyhow::Result<EnvFilter> {
+    if let Some(filter) = env.and_then(|env| EnvFilter::try_new(env).ok()) {
+        return Ok(filter);
+    }
+
+    EnvFilter::try_new(configured).with_context(|| format!("invalid log filter: {}", configured))
+}
+
+/// Sets up stdout and, when a directory is configured, rotating file logging.
+///
+/// `RUST_LOG` takes precedence over the configured filter. The returned guard flushes the file
+/// writer and has to be kept alive until exit.
+pub fn init(config: &LogConfig) -> anyhow::Result<Option<WorkerGuard>> {
+    let env = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let filter = filter(&config.filter, env.as_deref())?;
+
+    let mut layers = vec![fmt_layer(std::io::stdout, config.json, true)];
+
+    let guard = match &config.dir {
+        Some(dir) => {
+            let mut appender = RollingFileAppender::builder()
+                .rotation(config.rotation.into())
+                .filename_prefix("callpu")
+                .filename_suffix("log");
+            if let Some(max_files) = config.max_files {
+                appender = appender.max_log_files(max_files);
+            }
+            let appender = appender
+                .build(dir)
+                .with_context(|| format!("failed to open log directory {}", dir.display()))?;
+
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            layers.push(fmt_layer(writer, config.json, false));
+            Some(guard)
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry().with(layers).with(filter).init();
+
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn log(json: bool) -> String {
+            let captured = Captured::default();
+            let writer = captured.clone();
+            let subscriber =
+                tracing_subscriber::registry().with(fmt_layer(move || writer.clone(), json, false));
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::info!(chat_id = -1, "call sent");
+            });
+
+            String::from_utf8(captured.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter("info", None).unwrap().to_string(), "info");
+        assert_eq!(
+            filter("info", Some("callpu=debug")).unwrap().to_string(),
+            "callpu=debug"
+        );
+        // A broken RUST_LOG falls back to the config, a broken config is an error.
+        assert_eq!(filter("warn", Some("callpu=loud")).unwrap().to_string(), "warn");
+        assert!(filter("callpu=loud", None).is_err());
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = Captured::log(true);
+        let entry: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(entry["level"], "INFO");
+        assert_eq!(entry["fields"]["message"], "call sent");
+        assert_eq!(entry["fields"]["chat_id"], -1);
+
+        let line = Captured::log(false);
+        assert!(serde_json::from_str::<serde_json::Value>(line.trim()).is_err());
+        assert!(line.contains("call sent chat_id=-1"));
+    }
+}
@@ -0,0 +1,212 @@
+use std::sync::{LazyLock, Mutex};
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use chrono::{DateTime, Local};
+use prometheus::{
+    Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use teloxide::{RequestError, types::UpdateKind};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Updates received, by update kind.
+    pub updates: IntCounterVec,
+    /// Commands handled, by command name and whether it came as `/command` or a short trigger.
+    pub commands: IntCounterVec,
+    pub calls: IntCounter,
+    /// Number of users mentioned per call.
+    pub roster_size: Histogram,
+    /// Captchas by outcome: issued, passed, failed or timed_out.
+    pub captchas: IntCounterVec,
+    /// Telegram API errors, by error type.
+    pub api_errors: IntCounterVec,
+    pub send_latency: Histogram,
+    pub pending_deletions: IntGauge,
+    last_api_error: Mutex<Option<(DateTime<Local>, String)>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("callpu".to_string()), None)
+            .expect("invalid metrics prefix");
+
+        let updates = IntCounterVec::new(
+            Opts::new("updates_total", "Updates received by kind"),
+            &["kind"],
+        )
+        .unwrap();
+        let commands = IntCounterVec::new(
+            Opts::new("commands_total", "Commands handled"),
+            &["command", "via"],
+        )
+        .unwrap();
+        let calls = IntCounter::new("calls_total", "Calls made").unwrap();
+        let roster_size = Histogram::with_opts(
+            HistogramOpts::new("call_roster_size", "Users mentioned per call")
+                .buckets(vec![1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 34.0]),
+        )
+        .unwrap();
+        let captchas = IntCounterVec::new(
+            Opts::new("captchas_total", "Captchas by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let api_errors = IntCounterVec::new(
+            Opts::new("api_errors_total", "Telegram API errors by type"),
+            &["type"],
+        )
+        .unwrap();
+        let send_latency = Histogram::with_opts(HistogramOpts::new(
+            "send_latency_seconds",
+            "Latency of sending a message",
+        ))
+        .unwrap();
+        let pending_deletions = IntGauge::new(
+            "pending_deletions",
+            "Messages waiting to be deleted",
+        )
+        .unwrap();
+
+        registry.register(Box::new(updates.clone())).unwrap();
+        registry.register(Box::new(commands.clone())).unwrap();
+        registry.register(Box::new(calls.clone())).unwrap();
+        registry.register(Box::new(roster_size.clone())).unwrap();
+        registry.register(Box::new(captchas.clone())).unwrap();
+        registry.register(Box::new(api_errors.clone())).unwrap();
+        registry.register(Box::new(send_latency.clone())).unwrap();
+        registry.register(Box::new(pending_deletions.clone())).unwrap();
+
+        Self {
+            registry,
+            updates,
+            commands,
+            calls,
+            roster_size,
+            captchas,
+            api_errors,
+            send_latency,
+            pending_deletions,
+            last_api_error: Mutex::default(),
+        }
+    }
+
+    pub fn update(&self, kind: &UpdateKind) {
+        let kind = match kind {
+            UpdateKind::Message(_) => "message",
+            UpdateKind::EditedMessage(_) => "edited_message",
+            UpdateKind::CallbackQuery(_) => "callback_query",
+            UpdateKind::MyChatMember(_) => "my_chat_member",
+            UpdateKind::ChatMember(_) => "chat_member",
+            UpdateKind::MessageReaction(_) => "message_reaction",
+            _ => "other",
+        };
+        self.updates.with_label_values(&[kind]).inc();
+    }
+
+    pub fn command(&self, command: &str, via: &str) {
+        self.commands.with_label_values(&[command, via]).inc();
+    }
+
+    pub fn captcha(&self, outcome: &str) {
+        self.captchas.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn api_error(&self, error: &RequestError) {
+        let kind = match error {
+            RequestError::Api(_) => "api",
+            RequestError::MigrateToChatId(_) => "migrate_to_chat_id",
+            RequestError::RetryAfter(_) => "retry_after",
+            RequestError::Network(_) => "network",
+            RequestError::InvalidJson { .. } => "invalid_json",
+            RequestError::Io(_) => "io",
+        };
+        self.api_errors.with_label_values(&[kind]).inc();
+        *self.last_api_error.lock().unwrap() = Some((Local::now(), error.to_string()));
+    }
+
+    pub fn last_api_error(&self) -> Option<(DateTime<Local>, String)> {
+        self.last_api_error.lock().unwrap().clone()
+    }
+
+    fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_else(|e| format!("# failed to encode metrics: {}\n", e))
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                METRICS.encode(),
+            )
+                .into_response()
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::{
+        ApiError,
+        types::{ChatId, Update},
+    };
+
+    use super::*;
+
+    fn update(kind: &str) -> Update {
+        serde_json::from_value(serde_json::json!({
+            "update_id": 1,
+            kind: {
+                "message_id": 10,
+                "date": 1760800000,
+                "chat": { "id": -1, "type": "group", "title": "test" },
+                "text": "c",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_update_kinds() {
+        let metrics = Metrics::new();
+        metrics.update(&update("message").kind);
+        metrics.update(&update("message").kind);
+        metrics.update(&update("edited_message").kind);
+        metrics.update(&update("channel_post").kind);
+
+        let count = |kind: &str| metrics.updates.with_label_values(&[kind]).get();
+        assert_eq!(count("message"), 2);
+        assert_eq!(count("edited_message"), 1);
+        assert_eq!(count("other"), 1);
+    }
+
+    #[test]
+    fn test_api_errors() {
+        let metrics = Metrics::new();
+        assert!(metrics.last_api_error().is_none());
+
+        metrics.api_error(&RequestError::Api(ApiError::BotBlocked));
+        metrics.api_error(&RequestError::MigrateToChatId(ChatId(-100)));
+
+        let count = |kind: &str| metrics.api_errors.with_label_values(&[kind]).get();
+        assert_eq!(count("api"), 1);
+        assert_eq!(count("migrate_to_chat_id"), 1);
+        assert!(
+            metrics
+                .encode()
+                .contains("callpu_api_errors_total{type=\"api\"} 1")
+        );
+
+        let (_, error) = metrics.last_api_error().unwrap();
+        assert_eq!(
+            error,
+            RequestError::MigrateToChatId(ChatId(-100)).to_string()
+        );
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::Mutex;
+
+use teloxide::types::ChatId;
+
+/// Migrations noticed from `MigrateToChatId` errors, where the bot state isn't at hand. The bot
+/// picks them up with [`take_reported`] before handling the next update.
+static REPORTED: Mutex<Vec<(ChatId, ChatId)>> = Mutex::new(Vec::new());
+
+/// Notes that the group `from` became the supergroup `to`.
+pub fn report(from: ChatId, to: ChatId) {
+    let mut reported = REPORTED.lock().unwrap();
+    if !reported.contains(&(from, to)) {
+        reported.push((from, to));
+    }
+}
+
+pub fn take_reported() -> Vec<(ChatId, ChatId)> {
+    std::mem::take(&mut *REPORTED.lock().unwrap())
+}
@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::Router;
+
+/// Serves the monitoring endpoints on their own local listener, separate from the webhook.
+pub async fn serve(address: SocketAddr, router: Router) -> anyhow::Result<()> {
+    let tcp = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("failed to bind {}", address))?;
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(tcp, router).await {
+            tracing::error!("monitoring server error: {}", e);
+        }
+    });
+
+    tracing::info!("Monitoring listening on {}", address);
+
+    Ok(())
+}
@@ -1,26 +1,14 @@
 use teloxide::{types::User, utils::html};
 
-pub trait ReplaceUserExt {
-    fn replace_user(&self, user: User) -> String;
-}
-
-impl<T> ReplaceUserExt for T
-where
-    T: ToString,
-{
-    fn replace_user(&self, user: User) -> String {
-        self.to_string().replace(
-            "#User#",
-            &user_mention(&user)
-        )
-    }
-}
-
-
-fn user_mention(user: &User) -> String {
+/// `@username` when the user has one, otherwise an HTML link with the escaped full name.
+pub fn user_mention(user: &User) -> String {
     if let Some(mention) = &user.mention() {
         mention.to_owned()
     } else {
-        html::user_mention(user.id, user.full_name().as_str())
+        format!(
+            "<a href=\"tg://user?id={}\">{}</a>",
+            user.id,
+            html::escape(user.full_name().as_str())
+        )
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use teloxide::{ApiError, RequestError, types::UserId};
+
+/// Users who started the bot privately and want calls sent to them as direct messages too.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Subscribers {
+    users: Vec<UserId>,
+}
+
+impl Subscribers {
+    /// Returns whether the user wasn't subscribed yet.
+    pub fn subscribe(&mut self, user_id: UserId) -> bool {
+        if self.users.contains(&user_id) {
+            return false;
+        }
+        self.users.push(user_id);
+        true
+    }
+
+    /// Returns whether the user was subscribed.
+    pub fn unsubscribe(&mut self, user_id: UserId) -> bool {
+        let before = self.users.len();
+        self.users.retain(|&u| u != user_id);
+        self.users.len() != before
+    }
+
+    pub fn is_subscribed(&self, user_id: UserId) -> bool {
+        self.users.contains(&user_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+}
+
+/// Whether a failed direct message means the bot can't reach the user anymore, e.g. because they
+/// blocked it or deleted their account.
+pub fn is_unreachable(e: &RequestError) -> bool {
+    match e {
+        RequestError::Api(
+            ApiError::BotBlocked | ApiError::UserDeactivated | ApiError::CantInitiateConversation,
+        ) => true,
+        // Wordings teloxide doesn't know yet, e.g. the 403 for users who never started the bot.
+        RequestError::Api(ApiError::Unknown(description)) => description.starts_with("Forbidden:"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe() {
+        let mut subscribers = Subscribers::default();
+        assert!(subscribers.subscribe(UserId(1)));
+        assert!(!subscribers.subscribe(UserId(1)));
+        assert!(subscribers.subscribe(UserId(2)));
+        assert!(subscribers.unsubscribe(UserId(2)));
+        assert!(!subscribers.unsubscribe(UserId(2)));
+
+        // Opting in and out survives a restart.
+        let json = serde_json::to_string(&subscribers).unwrap();
+        let restored: Subscribers = serde_json::from_str(&json).unwrap();
+        assert!(restored.is_subscribed(UserId(1)));
+        assert!(!restored.is_subscribed(UserId(2)));
+        assert_eq!(restored.len(), 1);
+    }
+
+    #[test]
+    fn test_is_unreachable() {
+        let api = |description: &str| {
+            RequestError::Api(serde_json::from_value(serde_json::json!(description)).unwrap())
+        };
+
+        assert!(is_unreachable(&api(
+            "Forbidden: bot was blocked by the user"
+        )));
+        assert!(is_unreachable(&api(
+            "Forbidden: bot can't initiate conversation with a user"
+        )));
+        assert!(!is_unreachable(&api("Bad Request: message text is empty")));
+        assert!(!is_unreachable(&RequestError::MigrateToChatId(
+            teloxide::types::ChatId(-100)
+        )));
+    }
+}
@@ -1,4 +1,3 @@
-pub const QUESTION_HINT: &str = "Oh No!\n#User# 您触发了人机验证，请判断以下表达式的值是否为真（回答 true）或假（回答 false）：\n";
 pub const QUESTION_MAP: [(&str, bool); 15] = [
     ("(9007199254740992u64 as f64 + 1.0f64) == (9007199254740992u64 as f64)", true),
     ("(9007199254740992u64 as f64 + 2.0f64) == (9007199254740994u64 as f64)", true),
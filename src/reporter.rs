@@ -0,0 +1,158 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use teloxide::{prelude::*, types::ParseMode, utils::html};
+
+use crate::config::AdminConfig;
+
+/// Posts handler errors and lifecycle notices to the admin chat.
+pub struct ErrorReporter {
+    bot: teloxide::Bot,
+    chat_id: ChatId,
+    dedup_window: Duration,
+    max_per_minute: usize,
+    state: Mutex<ReporterState>,
+}
+
+#[derive(Default)]
+struct ReporterState {
+    /// Last report time and suppressed repeats, by command and error chain.
+    recent: HashMap<String, (Instant, u32)>,
+    /// When the reports of the last minute were sent.
+    sent: VecDeque<Instant>,
+}
+
+impl ErrorReporter {
+    pub fn new(bot: teloxide::Bot, config: &AdminConfig) -> Option<Self> {
+        let chat_id = config.chat_id?;
+
+        Some(Self {
+            bot,
+            chat_id: ChatId(chat_id),
+            dedup_window: Duration::from_secs(config.dedup_window_secs),
+            max_per_minute: config.max_reports_per_minute,
+            state: Mutex::default(),
+        })
+    }
+
+    /// Decides whether a report goes out, returning how many identical ones were suppressed
+    /// since the last time it did.
+    fn admit(&self, key: String) -> Option<u32> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if let Some((at, suppressed)) = state.recent.get_mut(&key)
+            && now.duration_since(*at) < self.dedup_window
+        {
+            *suppressed += 1;
+            return None;
+        }
+
+        while state
+            .sent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= Duration::from_secs(60))
+        {
+            state.sent.pop_front();
+        }
+        if state.sent.len() >= self.max_per_minute {
+            state.recent.entry(key).or_insert((now, 0)).1 += 1;
+            return None;
+        }
+
+        state.sent.push_back(now);
+        let suppressed = state.recent.insert(key, (now, 0)).map(|(_, n)| n).unwrap_or(0);
+        state
+            .recent
+            .retain(|_, (at, n)| *n > 0 || now.duration_since(*at) < self.dedup_window);
+        Some(suppressed)
+    }
+
+    pub async fn report(&self, command: Option<&str>, chat_id: ChatId, error: &anyhow::Error) {
+        let chain = format!("{:#}", error);
+        let Some(suppressed) = self.admit(format!("{:?}\n{}", command, chain)) else {
+            return;
+        };
+
+        let mut text = format!(
+            "⚠️ <b>Handler error</b>\nCommand: {}\nChat: <code>{}</code>\nTime: {}\n<pre>{}</pre>",
+            html::escape(command.unwrap_or("-")),
+            chat_id,
+            chrono::Local::now().to_rfc3339(),
+            html::escape(&chain)
+        );
+        if suppressed > 0 {
+            text.push_str(&format!("\n{} identical reports suppressed", suppressed));
+        }
+
+        self.send(text).await;
+    }
+
+    pub async fn notice(&self, text: &str) {
+        self.send(html::escape(text)).await;
+    }
+
+    async fn send(&self, text: String) {
+        if let Err(e) = self
+            .bot
+            .send_message(self.chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            tracing::warn!("failed to post to admin chat: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reporter(dedup_window_secs: u64, max_reports_per_minute: usize) -> ErrorReporter {
+        let config = AdminConfig {
+            chat_id: Some(-1),
+            dedup_window_secs,
+            max_reports_per_minute,
+            ..AdminConfig::default()
+        };
+        ErrorReporter::new(teloxide::Bot::new("0:test"), &config).unwrap()
+    }
+
+    #[test]
+    fn test_disabled_without_chat() {
+        let bot = teloxide::Bot::new("0:test");
+        assert!(ErrorReporter::new(bot, &AdminConfig::default()).is_none());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let reporter = reporter(1, 10);
+        assert_eq!(reporter.admit("a".to_string()), Some(0));
+        assert_eq!(reporter.admit("a".to_string()), None);
+        assert_eq!(reporter.admit("a".to_string()), None);
+        assert_eq!(reporter.admit("b".to_string()), Some(0));
+
+        // Once the window is over, the next report tells how many were held back.
+        {
+            let mut state = reporter.state.lock().unwrap();
+            state.recent.get_mut("a").unwrap().0 -= Duration::from_secs(2);
+        }
+        assert_eq!(reporter.admit("a".to_string()), Some(2));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let reporter = reporter(1, 2);
+        assert_eq!(reporter.admit("a".to_string()), Some(0));
+        assert_eq!(reporter.admit("b".to_string()), Some(0));
+        assert_eq!(reporter.admit("c".to_string()), None);
+
+        for at in reporter.state.lock().unwrap().sent.iter_mut() {
+            *at -= Duration::from_secs(60);
+        }
+        assert_eq!(reporter.admit("d".to_string()), Some(0));
+    }
+}
@@ -0,0 +1,452 @@
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, ThreadId, User};
+
+use crate::call_map::UserRecord;
+use crate::i18n::Locale;
+
+/// Calls overdue by more than this, e.g. because the bot was down, are skipped instead of fired
+/// late.
+const MISSED_GRACE: chrono::Duration = chrono::Duration::minutes(30);
+
+/// Where the scheduler gets the time from, so tests can move it by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Repeat {
+    Once,
+    Daily,
+    Weekly(Weekday),
+}
+
+impl Repeat {
+    fn parse(word: &str) -> Option<Self> {
+        match word.to_ascii_lowercase().as_str() {
+            "daily" | "everyday" | "每天" => return Some(Self::Daily),
+            _ => {}
+        }
+
+        if let Some(day) = word
+            .strip_prefix("周")
+            .or_else(|| word.strip_prefix("星期"))
+        {
+            let day = match day {
+                "一" => Weekday::Mon,
+                "二" => Weekday::Tue,
+                "三" => Weekday::Wed,
+                "四" => Weekday::Thu,
+                "五" => Weekday::Fri,
+                "六" => Weekday::Sat,
+                "日" | "天" => Weekday::Sun,
+                _ => return None,
+            };
+            return Some(Self::Weekly(day));
+        }
+
+        word.parse().ok().map(Self::Weekly)
+    }
+
+    /// The first moment after `after` that falls on `time` in `tz`.
+    fn next_after(self, time: NaiveTime, tz: Tz, after: DateTime<Utc>) -> DateTime<Utc> {
+        let mut date = after.with_timezone(&tz).date_naive();
+        loop {
+            let on_day = match self {
+                Self::Weekly(day) => date.weekday() == day,
+                Self::Once | Self::Daily => true,
+            };
+
+            // Local times skipped by a DST change have no `earliest`, they move to the next day.
+            if on_day && let Some(at) = tz.from_local_datetime(&date.and_time(time)).earliest() {
+                let at = at.with_timezone(&Utc);
+                if at > after {
+                    return at;
+                }
+            }
+
+            date = date
+                .checked_add_days(Days::new(1))
+                .expect("schedule date out of range");
+        }
+    }
+}
+
+/// What `/callat` asked for, before it is pinned to a chat.
+#[derive(Debug, PartialEq)]
+pub struct ScheduleSpec {
+    pub time: NaiveTime,
+    pub repeat: Repeat,
+    pub reason: Option<String>,
+}
+
+impl ScheduleSpec {
+    /// Parses `[daily|<weekday>] HH:MM [reason]`, e.g. `21:30 来打牌`, `fri 20:00` or `周五 20:00`.
+    pub fn parse(args: &str) -> Option<Self> {
+        let (first, rest) = split_word(args)?;
+        let (repeat, time, rest) = match Repeat::parse(first) {
+            Some(repeat) => {
+                let (time, rest) = split_word(rest)?;
+                (repeat, time, rest)
+            }
+            None => (Repeat::Once, first, rest),
+        };
+
+        let time = NaiveTime::parse_from_str(&time.replace('：', ":"), "%H:%M").ok()?;
+        let reason = Some(rest.trim().to_string()).filter(|reason| !reason.is_empty());
+
+        Some(Self {
+            time,
+            repeat,
+            reason,
+        })
+    }
+}
+
+fn split_word(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+
+    Some(match s.find(char::is_whitespace) {
+        Some(end) => (&s[..end], &s[end..]),
+        None => (s, ""),
+    })
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u64,
+    pub chat_id: ChatId,
+    /// The forum topic the call is sent to.
+    #[serde(default)]
+    pub thread: Option<ThreadId>,
+    /// Calls on behalf of this user, who is left out of the mentions.
+    pub creator: UserRecord,
+    pub time: NaiveTime,
+    pub repeat: Repeat,
+    /// The timezone `time` is read in, fixed when the schedule is created.
+    pub tz: Tz,
+    pub reason: Option<String>,
+    pub next: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// One line for `/schedules`, e.g. `#3 周五 20:00 (Asia/Shanghai) 来打牌`.
+    pub fn describe(&self, locale: Locale) -> String {
+        let when = match self.repeat {
+            Repeat::Once => self
+                .next
+                .with_timezone(&self.tz)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            Repeat::Daily => format!(
+                "{} {}",
+                locale.tr("schedule-daily"),
+                self.time.format("%H:%M")
+            ),
+            Repeat::Weekly(day) => {
+                format!(
+                    "{} {}",
+                    locale.tr(weekday_key(day)),
+                    self.time.format("%H:%M")
+                )
+            }
+        };
+
+        let mut text = format!("#{} {} ({})", self.id, when, self.tz);
+        if let Some(reason) = &self.reason {
+            text.push(' ');
+            text.push_str(reason);
+        }
+        text
+    }
+}
+
+pub fn weekday_key(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "weekday-mon",
+        Weekday::Tue => "weekday-tue",
+        Weekday::Wed => "weekday-wed",
+        Weekday::Thu => "weekday-thu",
+        Weekday::Fri => "weekday-fri",
+        Weekday::Sat => "weekday-sat",
+        Weekday::Sun => "weekday-sun",
+    }
+}
+
+/// Scheduled calls of every chat.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Schedules {
+    last_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+impl Schedules {
+    pub fn add(
+        &mut self, chat_id: ChatId, thread: Option<ThreadId>, creator: &User, spec: ScheduleSpec,
+        tz: Tz, now: DateTime<Utc>,
+    ) -> &Schedule {
+        self.last_id += 1;
+        self.schedules.push(Schedule {
+            id: self.last_id,
+            chat_id,
+            thread,
+            creator: creator.into(),
+            time: spec.time,
+            repeat: spec.repeat,
+            tz,
+            reason: spec.reason,
+            next: spec.repeat.next_after(spec.time, tz, now),
+        });
+
+        self.schedules.last().unwrap()
+    }
+
+    pub fn get(&self, chat_id: ChatId, id: u64) -> Option<&Schedule> {
+        self.schedules
+            .iter()
+            .find(|schedule| schedule.chat_id == chat_id && schedule.id == id)
+    }
+
+    pub fn remove(&mut self, chat_id: ChatId, id: u64) -> Option<Schedule> {
+        let index = self
+            .schedules
+            .iter()
+            .position(|schedule| schedule.chat_id == chat_id && schedule.id == id)?;
+        Some(self.schedules.remove(index))
+    }
+
+    /// The chat's schedules, soonest first.
+    pub fn for_chat(&self, chat_id: ChatId) -> Vec<&Schedule> {
+        let mut schedules = self
+            .schedules
+            .iter()
+            .filter(|schedule| schedule.chat_id == chat_id)
+            .collect::<Vec<_>>();
+        schedules.sort_by_key(|schedule| schedule.next);
+        schedules
+    }
+
+    pub fn len(&self) -> usize {
+        self.schedules.len()
+    }
+
+    /// Takes the current names of a user for the schedules they created. Returns whether any
+    /// name changed.
+    pub fn refresh_user(&mut self, user: &User) -> bool {
+        let mut changed = false;
+        for schedule in &mut self.schedules {
+            changed |= schedule.creator.rename(user);
+        }
+        changed
+    }
+
+    /// Moves the schedules of a group that became a supergroup, returning how many there were.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) -> usize {
+        let mut moved = 0;
+        for schedule in self.schedules.iter_mut().filter(|schedule| schedule.chat_id == from) {
+            schedule.chat_id = to;
+            moved += 1;
+        }
+        moved
+    }
+
+    /// Drops every schedule of the chat, returning how many there were.
+    pub fn remove_chat(&mut self, chat_id: ChatId) -> usize {
+        let before = self.schedules.len();
+        self.schedules.retain(|schedule| schedule.chat_id != chat_id);
+        before - self.schedules.len()
+    }
+
+    /// Takes the calls due at `now`. Recurring schedules move on to their next time, one-off
+    /// ones are dropped, and ones missed by more than [`MISSED_GRACE`] are not returned.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<Schedule> {
+        let mut due = Vec::new();
+
+        self.schedules.retain_mut(|schedule| {
+            if schedule.next > now {
+                return true;
+            }
+
+            if now - schedule.next <= MISSED_GRACE {
+                due.push(schedule.clone());
+            } else {
+                tracing::info!(
+                    "skipping schedule #{} missed at {}",
+                    schedule.id,
+                    schedule.next
+                );
+            }
+
+            match schedule.repeat {
+                Repeat::Once => false,
+                repeat => {
+                    schedule.next = repeat.next_after(schedule.time, schedule.tz, now);
+                    true
+                }
+            }
+        });
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Asia::Shanghai;
+
+    use super::*;
+    use crate::test_util::{ManualClock, user};
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            ScheduleSpec::parse("21:30 来打牌 今晚"),
+            Some(ScheduleSpec {
+                time: time("21:30"),
+                repeat: Repeat::Once,
+                reason: Some("来打牌 今晚".to_string()),
+            })
+        );
+        assert_eq!(
+            ScheduleSpec::parse("Friday 20:00").map(|spec| spec.repeat),
+            Some(Repeat::Weekly(Weekday::Fri))
+        );
+        assert_eq!(
+            ScheduleSpec::parse("周五 20：00").map(|spec| (spec.repeat, spec.time)),
+            Some((Repeat::Weekly(Weekday::Fri), time("20:00")))
+        );
+        assert_eq!(
+            ScheduleSpec::parse("daily 08:00").map(|spec| spec.repeat),
+            Some(Repeat::Daily)
+        );
+        assert!(ScheduleSpec::parse("").is_none());
+        assert!(ScheduleSpec::parse("fri").is_none());
+        assert!(ScheduleSpec::parse("25:00").is_none());
+        assert!(ScheduleSpec::parse("来打牌 21:30").is_none());
+    }
+
+    #[test]
+    fn test_once_fires_in_chat_timezone() {
+        // 20:00 in Shanghai.
+        let clock = ManualClock::at("2026-10-16T12:00:00Z");
+        let mut schedules = Schedules::default();
+        let spec = ScheduleSpec::parse("21:30").unwrap();
+        let next = schedules
+            .add(ChatId(-1), None, &user(1), spec, Shanghai, clock.now())
+            .next;
+        assert_eq!(
+            next,
+            "2026-10-16T13:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        clock.advance(chrono::Duration::minutes(89));
+        assert!(schedules.take_due(clock.now()).is_empty());
+
+        clock.advance(chrono::Duration::minutes(1));
+        assert_eq!(schedules.take_due(clock.now()).len(), 1);
+        assert_eq!(schedules.len(), 0);
+    }
+
+    #[test]
+    fn test_once_earlier_time_is_tomorrow() {
+        let clock = ManualClock::at("2026-10-16T14:00:00Z");
+        let mut schedules = Schedules::default();
+        let spec = ScheduleSpec::parse("21:30").unwrap();
+        let next = schedules
+            .add(ChatId(-1), None, &user(1), spec, Shanghai, clock.now())
+            .next;
+        assert_eq!(
+            next,
+            "2026-10-17T13:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_weekly_repeats() {
+        // Wednesday.
+        let clock = ManualClock::at("2026-10-14T00:00:00Z");
+        let mut schedules = Schedules::default();
+        let spec = ScheduleSpec::parse("fri 20:00").unwrap();
+        schedules.add(ChatId(-1), None, &user(1), spec, Shanghai, clock.now());
+
+        for friday in ["2026-10-16T12:00:00Z", "2026-10-23T12:00:00Z"] {
+            let friday = friday.parse::<DateTime<Utc>>().unwrap();
+            assert_eq!(schedules.for_chat(ChatId(-1))[0].next, friday);
+
+            clock.advance(friday - clock.now());
+            assert_eq!(schedules.take_due(clock.now()).len(), 1);
+            assert!(schedules.take_due(clock.now()).is_empty());
+        }
+        assert_eq!(schedules.len(), 1);
+    }
+
+    #[test]
+    fn test_missed_calls_are_skipped() {
+        let clock = ManualClock::at("2026-10-16T12:00:00Z");
+        let mut schedules = Schedules::default();
+        schedules.add(
+            ChatId(-1),
+            None,
+            &user(1),
+            ScheduleSpec::parse("21:30").unwrap(),
+            Shanghai,
+            clock.now(),
+        );
+        schedules.add(
+            ChatId(-1),
+            None,
+            &user(1),
+            ScheduleSpec::parse("daily 21:30").unwrap(),
+            Shanghai,
+            clock.now(),
+        );
+
+        // The bot was down for a day.
+        clock.advance(chrono::Duration::hours(26));
+        assert!(schedules.take_due(clock.now()).is_empty());
+
+        let remaining = schedules.for_chat(ChatId(-1));
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(
+            remaining[0].next,
+            "2026-10-18T13:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_remove_checks_chat() {
+        let mut schedules = Schedules::default();
+        let id = schedules
+            .add(
+                ChatId(-1),
+                None,
+                &user(1),
+                ScheduleSpec::parse("21:30").unwrap(),
+                Shanghai,
+                Utc::now(),
+            )
+            .id;
+
+        assert!(schedules.remove(ChatId(-2), id).is_none());
+        assert!(schedules.remove(ChatId(-1), id).is_some());
+        assert!(schedules.get(ChatId(-1), id).is_none());
+    }
+}
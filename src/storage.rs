@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use crate::{
+    call_map::CallMap, calls::ActiveCalls, deletion::PendingDeletion, history::History,
+    lobby::Lobbies, notify::Subscribers, schedule::Schedules,
+};
+
+/// Everything that has to survive a restart.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub callmap: CallMap,
+    pub pending_deletions: Vec<PendingDeletion>,
+    pub schedules: Schedules,
+    pub lobbies: Lobbies,
+    pub history: History,
+    pub active_calls: ActiveCalls,
+    pub subscribers: Subscribers,
+    /// Groups that became supergroups, as (old id, new id).
+    pub migrations: Vec<(ChatId, ChatId)>,
+}
+
+#[derive(Clone)]
+pub struct Storage {
+    path: PathBuf,
+}
+
+impl Storage {
+    pub fn new(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => dirs::data_dir()
+                .context("no data directory on this platform, set storage.path")?
+                .join("callpu")
+                .join("state.json"),
+        };
+
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A state file that can't be parsed is moved aside and the bot starts from scratch.
+    pub fn load(&self) -> anyhow::Result<State> {
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.path.display()));
+            }
+        };
+
+        match serde_json::from_slice(&content) {
+            Ok(state) => Ok(state),
+            Err(e) => {
+                let corrupt = self.path.with_extension("json.corrupt");
+                tracing::error!(
+                    "failed to parse {}, starting empty and keeping it as {}: {}",
+                    self.path.display(),
+                    corrupt.display(),
+                    e
+                );
+                std::fs::rename(&self.path, &corrupt)
+                    .with_context(|| format!("failed to move {}", self.path.display()))?;
+                Ok(State::default())
+            }
+        }
+    }
+
+    /// Creates and removes a probe file next to the state file.
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        let probe = self.path.with_extension("probe");
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        std::fs::write(&probe, b"")
+            .with_context(|| format!("failed to write {}", probe.display()))?;
+        std::fs::remove_file(&probe)
+            .with_context(|| format!("failed to remove {}", probe.display()))?;
+
+        Ok(())
+    }
+
+    /// Writes to a temporary file first so a crash mid-write never leaves a truncated state.
+    pub fn save(&self, state: &State) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::UserId;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn storage(dir: &TempDir) -> Storage {
+        Storage::new(Some(&dir.path().join("state.json"))).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new("round-trip");
+        let storage = storage(&dir);
+
+        let mut state = State::default();
+        state.subscribers.subscribe(UserId(1));
+        state.migrations.push((ChatId(-1), ChatId(-100)));
+        storage.save(&state).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert!(loaded.subscribers.is_subscribed(UserId(1)));
+        assert_eq!(loaded.migrations, [(ChatId(-1), ChatId(-100))]);
+    }
+
+    #[test]
+    fn test_load_fallback() {
+        let dir = TempDir::new("fallback");
+        let storage = storage(&dir);
+        assert_eq!(storage.load().unwrap().subscribers.len(), 0);
+
+        std::fs::write(storage.path(), b"{\"callmap\": ").unwrap();
+        assert_eq!(storage.load().unwrap().subscribers.len(), 0);
+        // The broken file is kept for inspection instead of being overwritten.
+        assert!(!storage.path().exists());
+        assert!(storage.path().with_extension("json.corrupt").exists());
+    }
+
+    #[test]
+    fn test_save_leaves_no_temporary_file() {
+        let dir = TempDir::new("atomic");
+        let storage = storage(&dir);
+        storage.save(&State::default()).unwrap();
+        storage.save(&State::default()).unwrap();
+
+        let files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["state.json"]);
+    }
+}
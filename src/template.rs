@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, bail};
+use teloxide::{
+    types::{ChatId, User},
+    utils::html,
+};
+
+use crate::{i18n::Locale, msg_prelude::user_mention};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Var {
+    User,
+    Registrar,
+    Count,
+    Roster,
+    Remaining,
+    Reason,
+    Schedule,
+    Game,
+    Capacity,
+    Seed,
+    Players,
+    Days,
+    Stats,
+    History,
+    Chat,
+    Link,
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "user" => Some(Self::User),
+            "registrar" => Some(Self::Registrar),
+            "count" => Some(Self::Count),
+            "roster" => Some(Self::Roster),
+            "remaining" => Some(Self::Remaining),
+            "reason" => Some(Self::Reason),
+            "schedule" => Some(Self::Schedule),
+            "game" => Some(Self::Game),
+            "capacity" => Some(Self::Capacity),
+            "seed" => Some(Self::Seed),
+            "players" => Some(Self::Players),
+            "days" => Some(Self::Days),
+            "stats" => Some(Self::Stats),
+            "history" => Some(Self::History),
+            "chat" => Some(Self::Chat),
+            "link" => Some(Self::Link),
+            _ => None,
+        }
+    }
+}
+
+/// Every message the bot sends and the placeholders its template may use.
+const MESSAGES: &[(&str, &[Var])] = &[
+    ("not-white-group", &[]),
+    ("blacklist-already", &[Var::User]),
+    ("blacklist-done", &[Var::User]),
+    ("unblacklist-not-in", &[Var::User]),
+    ("unblacklist-done", &[Var::User]),
+    ("captcha-pending", &[Var::User]),
+    ("captcha-hint", &[Var::User]),
+    ("captcha-timeout", &[Var::User]),
+    ("captcha-failed", &[Var::User]),
+    ("whoregistered-none", &[Var::User]),
+    (
+        "whoregistered-found",
+        &[Var::User, Var::Registrar, Var::History],
+    ),
+    ("whoregistered-forgot", &[Var::User, Var::History]),
+    ("audit-registered-by", &[]),
+    ("audit-self", &[]),
+    ("audit-anonymous", &[]),
+    ("audit-via-command", &[]),
+    ("audit-via-shortcut", &[]),
+    ("audit-via-reply", &[]),
+    ("audit-left", &[]),
+    ("audit-left-chat", &[]),
+    ("call-empty", &[Var::User]),
+    ("call-not-in-list", &[Var::User]),
+    ("call-no-others", &[Var::User]),
+    ("call-message", &[Var::User, Var::Count, Var::Roster]),
+    (
+        "call-message-reason",
+        &[Var::User, Var::Count, Var::Roster, Var::Reason],
+    ),
+    ("call-reping", &[Var::User, Var::Count, Var::Roster]),
+    ("rsvp-coming", &[]),
+    ("rsvp-not-coming", &[]),
+    ("rsvp-later", &[]),
+    ("cancelcall-none", &[Var::User]),
+    ("cancelcall-forbidden", &[Var::User]),
+    ("cancelcall-done", &[Var::User]),
+    ("closecall-none", &[Var::User]),
+    ("closecall-forbidden", &[Var::User]),
+    ("closecall-done", &[Var::User]),
+    ("call-cancelled", &[Var::User]),
+    ("call-closed", &[Var::User]),
+    ("callat-usage", &[Var::User]),
+    ("callat-too-many", &[Var::User]),
+    ("callat-done", &[Var::User, Var::Schedule]),
+    ("schedules-empty", &[Var::User]),
+    ("schedules-list", &[Var::User, Var::Schedule]),
+    ("unschedule-usage", &[Var::User]),
+    ("unschedule-not-found", &[Var::User]),
+    ("unschedule-forbidden", &[Var::User, Var::Schedule]),
+    ("unschedule-done", &[Var::User, Var::Schedule]),
+    ("lobby-usage", &[Var::User]),
+    (
+        "lobby-message",
+        &[
+            Var::User,
+            Var::Game,
+            Var::Count,
+            Var::Capacity,
+            Var::Remaining,
+            Var::Roster,
+        ],
+    ),
+    ("lobby-waitlist", &[Var::Count, Var::Roster]),
+    ("lobby-ready-status", &[Var::Count, Var::Remaining]),
+    ("lobby-closed", &[]),
+    (
+        "lobby-open",
+        &[Var::User, Var::Game, Var::Remaining, Var::Roster],
+    ),
+    ("lobby-full", &[Var::Game, Var::Roster]),
+    ("lobby-promoted", &[Var::User, Var::Game]),
+    (
+        "lobby-ready-check",
+        &[Var::Game, Var::Remaining, Var::Roster],
+    ),
+    ("lobby-ready-timeout", &[Var::Game, Var::Roster]),
+    ("lobby-start", &[Var::Game, Var::Roster]),
+    ("lobby-button-join", &[]),
+    ("lobby-button-leave", &[]),
+    ("lobby-button-check", &[]),
+    ("lobby-button-ready", &[]),
+    ("lobby-button-close", &[]),
+    ("lobby-joined", &[]),
+    ("lobby-waitlisted", &[]),
+    ("lobby-already-in", &[]),
+    ("lobby-left", &[]),
+    ("lobby-not-in", &[]),
+    ("lobby-host-only", &[]),
+    ("lobby-gone", &[]),
+    ("lobby-check-started", &[]),
+    ("lobby-no-check", &[]),
+    ("lobby-ready-done", &[]),
+    ("lobby-closed-done", &[]),
+    ("draw-empty", &[Var::User]),
+    ("draw-seed", &[Var::Seed, Var::Count, Var::Players]),
+    ("teams-usage", &[Var::User, Var::Count]),
+    ("teams-header", &[Var::User, Var::Count]),
+    ("teams-team", &[Var::Count, Var::Roster]),
+    ("pick-result", &[Var::User, Var::Roster]),
+    ("pick-result-reason", &[Var::User, Var::Reason, Var::Roster]),
+    ("order-result", &[Var::User, Var::Roster]),
+    ("stats-empty", &[Var::User, Var::Days]),
+    ("stats-empty-all", &[Var::User]),
+    (
+        "stats-message",
+        &[Var::User, Var::Count, Var::Days, Var::Stats],
+    ),
+    ("stats-message-all", &[Var::User, Var::Count, Var::Stats]),
+    ("stats-callers", &[]),
+    ("stats-called", &[]),
+    ("stats-hours", &[]),
+    ("stats-responses", &[]),
+    ("register-other-already", &[Var::User, Var::Registrar]),
+    ("register-other-blacklisted", &[Var::User, Var::Registrar]),
+    ("register-self-already", &[Var::User]),
+    ("register-self-blacklisted", &[Var::User]),
+    ("register-done", &[Var::User, Var::Registrar]),
+    ("notify-private-only", &[Var::User]),
+    ("notify-on", &[Var::User]),
+    ("notify-off", &[Var::User]),
+    ("notify-status-on", &[Var::User]),
+    ("notify-status-off", &[Var::User]),
+    ("notify-usage", &[Var::User]),
+    ("dm-call", &[Var::User, Var::Chat, Var::Link]),
+    (
+        "dm-call-reason",
+        &[Var::User, Var::Chat, Var::Link, Var::Reason],
+    ),
+    ("dm-reping", &[Var::User, Var::Link]),
+    ("leave-not-registered", &[Var::User]),
+    ("leave-done", &[Var::User]),
+    ("status-forbidden", &[Var::User]),
+    ("status-sent", &[Var::User]),
+    ("status-start-private", &[Var::User]),
+    ("help-unknown", &[Var::User]),
+];
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Text(String),
+    Var(Var),
+}
+
+/// A message template with `{name}` placeholders, `{{` and `}}` stand for literal braces.
+///
+/// The template text itself is Telegram HTML, values substituted into it are escaped, except
+/// `{stats}` and `{history}`, which are put together from templates themselves.
+#[derive(Clone, Debug)]
+pub struct Template(Vec<Segment>);
+
+impl Template {
+    pub fn parse(src: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = src.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some(end) = rest.find('}') else {
+                        bail!("unclosed placeholder in {:?}", src);
+                    };
+                    let name = &rest[..end];
+                    let Some(var) = Var::from_name(name) else {
+                        bail!("unknown placeholder {{{}}} in {:?}", name, src);
+                    };
+                    chars = rest[end + 1..].chars();
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Var(var));
+                }
+                '}' => bail!("unmatched }} in {:?}", src),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self(segments))
+    }
+
+    fn vars(&self) -> impl Iterator<Item = Var> + '_ {
+        self.0.iter().filter_map(|segment| match segment {
+            Segment::Var(var) => Some(*var),
+            Segment::Text(_) => None,
+        })
+    }
+
+    pub fn render(&self, args: &Args) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Var(Var::User) => {
+                    out.push_str(&args.user.map(user_mention).unwrap_or_default())
+                }
+                Segment::Var(Var::Registrar) => {
+                    out.push_str(&args.registrar.map(user_mention).unwrap_or_default())
+                }
+                Segment::Var(Var::Count) => {
+                    out.push_str(&args.count.map(|n| n.to_string()).unwrap_or_default())
+                }
+                Segment::Var(Var::Roster) => out.push_str(
+                    &args
+                        .roster
+                        .unwrap_or_default()
+                        .iter()
+                        .map(user_mention)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ),
+                Segment::Var(Var::Remaining) => {
+                    out.push_str(&args.remaining.map(|n| n.to_string()).unwrap_or_default())
+                }
+                Segment::Var(Var::Reason) => {
+                    out.push_str(&html::escape(args.reason.unwrap_or_default()))
+                }
+                Segment::Var(Var::Schedule) => {
+                    out.push_str(&html::escape(args.schedule.unwrap_or_default()))
+                }
+                Segment::Var(Var::Game) => {
+                    out.push_str(&html::escape(args.game.unwrap_or_default()))
+                }
+                Segment::Var(Var::Capacity) => {
+                    out.push_str(&args.capacity.map(|n| n.to_string()).unwrap_or_default())
+                }
+                Segment::Var(Var::Seed) => {
+                    out.push_str(&args.seed.map(|n| n.to_string()).unwrap_or_default())
+                }
+                Segment::Var(Var::Players) => {
+                    out.push_str(&html::escape(args.players.unwrap_or_default()))
+                }
+                Segment::Var(Var::Days) => {
+                    out.push_str(&args.days.map(|n| n.to_string()).unwrap_or_default())
+                }
+                Segment::Var(Var::Stats) => out.push_str(args.stats.unwrap_or_default()),
+                Segment::Var(Var::History) => out.push_str(args.history.unwrap_or_default()),
+                Segment::Var(Var::Chat) => {
+                    out.push_str(&html::escape(args.chat.unwrap_or_default()))
+                }
+                Segment::Var(Var::Link) => {
+                    out.push_str(&html::escape(args.link.unwrap_or_default()))
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Values for the placeholders of a [`Template`], unset ones render as nothing.
+#[derive(Clone, Copy, Default)]
+pub struct Args<'a> {
+    user: Option<&'a User>,
+    registrar: Option<&'a User>,
+    count: Option<usize>,
+    roster: Option<&'a [User]>,
+    remaining: Option<usize>,
+    reason: Option<&'a str>,
+    schedule: Option<&'a str>,
+    game: Option<&'a str>,
+    capacity: Option<usize>,
+    seed: Option<u64>,
+    players: Option<&'a str>,
+    days: Option<u64>,
+    stats: Option<&'a str>,
+    history: Option<&'a str>,
+    chat: Option<&'a str>,
+    link: Option<&'a str>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn user(mut self, user: &'a User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn registrar(mut self, registrar: &'a User) -> Self {
+        self.registrar = Some(registrar);
+        self
+    }
+
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn roster(mut self, roster: &'a [User]) -> Self {
+        self.roster = Some(roster);
+        self
+    }
+
+    pub fn remaining(mut self, remaining: usize) -> Self {
+        self.remaining = Some(remaining);
+        self
+    }
+
+    pub fn reason(mut self, reason: &'a str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    /// One or more schedules, as described by [`crate::schedule::Schedule::describe`].
+    pub fn schedule(mut self, schedule: &'a str) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    pub fn game(mut self, game: &'a str) -> Self {
+        self.game = Some(game);
+        self
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// The user ids of a draw's players in the order they were shuffled.
+    pub fn players(mut self, players: &'a str) -> Self {
+        self.players = Some(players);
+        self
+    }
+
+    pub fn days(mut self, days: u64) -> Self {
+        self.days = Some(days);
+        self
+    }
+
+    /// The `/stats` rankings, one line per entry, already HTML.
+    pub fn stats(mut self, stats: &'a str) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Registration events for `/whoregisteredme`, one line per event, already HTML.
+    pub fn history(mut self, history: &'a str) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// The group's title.
+    pub fn chat(mut self, chat: &'a str) -> Self {
+        self.chat = Some(chat);
+        self
+    }
+
+    pub fn link(mut self, link: &'a str) -> Self {
+        self.link = Some(link);
+        self
+    }
+}
+
+fn compile(key: &str, src: &str) -> anyhow::Result<Template> {
+    let Some((_, allowed)) = MESSAGES.iter().find(|(name, _)| *name == key) else {
+        bail!("unknown message {:?}", key);
+    };
+
+    let template = Template::parse(src)?;
+    if let Some(var) = template.vars().find(|var| !allowed.contains(var)) {
+        bail!("placeholder {:?} is not available in message {:?}", var, key);
+    }
+
+    Ok(template)
+}
+
+/// Where a message goes, which decides the catalog and the per-chat overrides to use.
+#[derive(Clone, Copy)]
+pub struct TextCtx {
+    pub locale: Locale,
+    pub chat_id: ChatId,
+}
+
+pub struct Templates {
+    catalogs: HashMap<Locale, HashMap<&'static str, Template>>,
+    chats: HashMap<ChatId, HashMap<String, Template>>,
+}
+
+impl Templates {
+    /// Moves the overrides of a group that became a supergroup.
+    pub fn migrate_chat(&mut self, from: ChatId, to: ChatId) {
+        if let Some(templates) = self.chats.remove(&from) {
+            self.chats.entry(to).or_insert(templates);
+        }
+    }
+
+    /// Compiles the catalogs and the per-chat overrides from the config, so a broken template
+    /// fails at startup instead of when the message is sent.
+    pub fn load(overrides: &HashMap<String, HashMap<String, String>>) -> anyhow::Result<Self> {
+        let mut catalogs = HashMap::new();
+        for locale in Locale::ALL {
+            let mut catalog = HashMap::new();
+            for &(key, _) in MESSAGES {
+                let template = compile(key, locale.tr(key))
+                    .with_context(|| format!("invalid {:?} catalog", locale))?;
+                catalog.insert(key, template);
+            }
+            catalogs.insert(locale, catalog);
+        }
+
+        let mut chats = HashMap::new();
+        for (chat_id, templates) in overrides {
+            let chat_id = ChatId(
+                chat_id
+                    .parse()
+                    .with_context(|| format!("invalid chat id in templates: {}", chat_id))?,
+            );
+
+            let mut compiled = HashMap::new();
+            for (key, src) in templates {
+                let template = compile(key, src)
+                    .with_context(|| format!("invalid template for chat {}", chat_id))?;
+                compiled.insert(key.clone(), template);
+            }
+            chats.insert(chat_id, compiled);
+        }
+
+        Ok(Self { catalogs, chats })
+    }
+
+    pub fn render(&self, ctx: TextCtx, key: &str, args: Args) -> String {
+        let template = self
+            .chats
+            .get(&ctx.chat_id)
+            .and_then(|templates| templates.get(key))
+            .or_else(|| self.catalogs.get(&ctx.locale).and_then(|catalog| catalog.get(key)));
+
+        match template {
+            Some(template) => template.render(&args),
+            None => {
+                tracing::warn!("no template for message {:?}", key);
+                key.to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::named_user;
+
+    #[test]
+    fn test_parse() {
+        assert!(Template::parse("{user} 来了 {{}}").is_ok());
+        assert!(Template::parse("{nobody}").is_err());
+        assert!(Template::parse("{user").is_err());
+        assert!(Template::parse("user}").is_err());
+    }
+
+    #[test]
+    fn test_render_escapes_values() {
+        let template = Template::parse("{user} 注册了 {{user}}").unwrap();
+        let user = named_user(1, "<b>#User#</b> {user}");
+
+        assert_eq!(
+            template.render(&Args::new().user(&user)),
+            "<a href=\"tg://user?id=1\">&lt;b&gt;#User#&lt;/b&gt; {user}</a> 注册了 {user}"
+        );
+    }
+
+    #[test]
+    fn test_placeholders_checked_per_message() {
+        assert!(compile("leave-done", "{user} 走了").is_ok());
+        assert!(compile("leave-done", "{roster}").is_err());
+        assert!(compile("rsvp-coming", "<b>来</b>").is_ok());
+        assert!(compile("lobby-button-join", "{user}").is_err());
+        assert!(compile("no-such-message", "hi").is_err());
+    }
+
+    #[test]
+    fn test_catalogs_compile() {
+        Templates::load(&HashMap::new()).unwrap();
+    }
+}
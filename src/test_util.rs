@@ -0,0 +1,64 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use teloxide::types::User;
+
+use crate::schedule::Clock;
+
+/// A regular user named `user<id>`.
+pub fn user(id: u64) -> User {
+    named_user(id, &format!("user{}", id))
+}
+
+pub fn named_user(id: u64, first_name: &str) -> User {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "is_bot": false,
+        "first_name": first_name,
+    }))
+    .unwrap()
+}
+
+/// A clock that only moves when told to.
+pub struct ManualClock(Mutex<DateTime<Utc>>);
+
+impl ManualClock {
+    pub fn at(rfc3339: &str) -> Self {
+        Self(Mutex::new(rfc3339.parse().unwrap()))
+    }
+
+    pub fn advance(&self, by: chrono::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// A fresh directory per test, removed again when it is dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("callpu-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
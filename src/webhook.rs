@@ -0,0 +1,161 @@
+use std::convert::Infallible;
+
+use anyhow::Context;
+use teloxide::{
+    prelude::*,
+    types::InputFile,
+    update_listeners::{
+        UpdateListener,
+        webhooks::{self, Options},
+    },
+};
+use tokio::net::TcpListener;
+
+use crate::{bot::ALLOWED_UPDATES, config::WebhookConfig};
+
+/// Starts the local HTTP listener and returns the update listener fed by it.
+///
+/// The secret token header is verified by the teloxide router before an update reaches us.
+pub async fn listener(
+    bot: teloxide::Bot, config: &WebhookConfig,
+) -> anyhow::Result<impl UpdateListener<Err = Infallible> + use<>> {
+    let url = reqwest::Url::parse(&config.url)
+        .with_context(|| format!("invalid webhook url: {}", config.url))?;
+
+    // Bound before Telegram is told about the webhook, so a bad address fails startup.
+    let tcp = TcpListener::bind(config.address)
+        .await
+        .with_context(|| format!("failed to bind {}", config.address))?;
+    let listener = serve(tcp, url.clone(), config).await?;
+
+    if config.set_webhook {
+        let mut request = bot
+            .set_webhook(url)
+            .allowed_updates(ALLOWED_UPDATES.to_vec());
+        if let Some(secret_token) = &config.secret_token {
+            request = request.secret_token(secret_token.clone());
+        }
+        if let Some(tls) = config.tls.as_ref().filter(|tls| tls.self_signed) {
+            request = request.certificate(InputFile::file(tls.cert.clone()));
+        }
+        request.await.context("failed to set webhook")?;
+    }
+
+    tracing::info!("Webhook listening on {}{}", config.address, config.path);
+
+    Ok(listener)
+}
+
+/// Serves the webhook route on an already bound listener.
+async fn serve(
+    tcp: TcpListener, url: reqwest::Url, config: &WebhookConfig,
+) -> anyhow::Result<impl UpdateListener<Err = Infallible> + use<>> {
+    let mut options = Options::new(tcp.local_addr()?, url).path(config.path.clone());
+    if let Some(secret_token) = &config.secret_token {
+        options = options.secret_token(secret_token.clone());
+    }
+
+    let (listener, stop_flag, app) = webhooks::axum_no_setup(options);
+
+    if let Some(tls) = &config.tls {
+        let rustls_config =
+            axum_server::tls_rustls::RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+                .await
+                .context("failed to load webhook tls certificate")?;
+
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                stop_flag.await;
+                handle.graceful_shutdown(None);
+            }
+        });
+
+        let server = axum_server::from_tcp_rustls(tcp.into_std()?, rustls_config).handle(handle);
+        tokio::spawn(async move {
+            if let Err(e) = server.serve(app.into_make_service()).await {
+                tracing::error!("webhook server error: {}", e);
+            }
+        });
+    } else {
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(tcp, app).with_graceful_shutdown(stop_flag).await {
+                tracing::error!("webhook server error: {}", e);
+            }
+        });
+    }
+
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use reqwest::StatusCode;
+    use teloxide::update_listeners::AsUpdateStream;
+
+    use super::*;
+
+    const UPDATE: &str = r#"{
+        "update_id": 1,
+        "message": {
+            "message_id": 10,
+            "date": 1760800000,
+            "chat": { "id": -1, "type": "group", "title": "test" },
+            "from": { "id": 2, "is_bot": false, "first_name": "user2" },
+            "text": "c"
+        }
+    }"#;
+
+    #[tokio::test]
+    async fn test_secret_token() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp.local_addr().unwrap();
+        let config = WebhookConfig {
+            address,
+            url: "https://example.com/webhook".to_string(),
+            path: "/webhook".to_string(),
+            secret_token: Some("secret".to_string()),
+            set_webhook: false,
+            tls: None,
+        };
+        let url = config.url.parse().unwrap();
+        let mut listener = serve(tcp, url, &config).await.unwrap();
+
+        let client = reqwest::Client::new();
+        let post = |secret: Option<&str>| {
+            let mut request = client
+                .post(format!("http://{}/webhook", address))
+                .header("content-type", "application/json")
+                .body(UPDATE);
+            if let Some(secret) = secret {
+                request = request.header("x-telegram-bot-api-secret-token", secret);
+            }
+            request.send()
+        };
+
+        assert_eq!(post(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            post(Some("wrong")).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(post(Some("secret")).await.unwrap().status(), StatusCode::OK);
+
+        // Only the update with the right token made it through.
+        let mut updates = std::pin::pin!(listener.as_stream());
+        let update = tokio::time::timeout(Duration::from_secs(5), updates.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.chat().unwrap().id, ChatId(-1));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), updates.next())
+                .await
+                .is_err()
+        );
+    }
+}